@@ -0,0 +1,326 @@
+use crate::machine::{Machine, NUM_REGISTERS, REGISTER_OFFSET, U15_MAX};
+use crate::parse::Token;
+
+/// An instruction argument, resolved when its block is compiled.
+#[derive(Clone, Copy, Debug)]
+enum Operand {
+    Literal(u16),
+
+    /// A register, by its address in memory
+    Register(usize),
+}
+
+/// An instruction that never jumps, does I/O or stops the machine, with its
+/// arguments resolved. Destinations are addresses known to be in bounds.
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Set(usize, Operand),
+    Push(Operand),
+    Pop(usize),
+    Eq(usize, Operand, Operand),
+    Gt(usize, Operand, Operand),
+    Add(usize, Operand, Operand),
+    Mult(usize, Operand, Operand),
+    Mod(usize, Operand, Operand),
+    And(usize, Operand, Operand),
+    Or(usize, Operand, Operand),
+    Not(usize, Operand),
+    Rmem(usize, Operand),
+    Wmem(Operand, Operand),
+    Noop,
+}
+
+impl Op {
+    /// Compile a token, or `None` if it has to be left to the interpreter
+    /// because it changes control flow, does I/O, or has an argument that
+    /// the interpreter stops with an error on.
+    fn compile(token: Token) -> Option<Self> {
+        let operand = |arg: u16| match arg {
+            0..REGISTER_OFFSET => Some(Operand::Literal(arg)),
+            _ if arg < REGISTER_OFFSET + NUM_REGISTERS => Some(Operand::Register(arg as usize)),
+            _ => None,
+        };
+        let destination =
+            |arg: u16| (arg < REGISTER_OFFSET + NUM_REGISTERS).then_some(arg as usize);
+
+        Some(match token {
+            Token::Set(register, value) => {
+                if register < REGISTER_OFFSET {
+                    return None;
+                }
+
+                Self::Set(destination(register)?, operand(value)?)
+            }
+            Token::Push(value) => Self::Push(operand(value)?),
+            Token::Pop(d) => Self::Pop(destination(d)?),
+            Token::Eq(d, lhs, rhs) => Self::Eq(destination(d)?, operand(lhs)?, operand(rhs)?),
+            Token::Gt(d, lhs, rhs) => Self::Gt(destination(d)?, operand(lhs)?, operand(rhs)?),
+            Token::Add(d, lhs, rhs) => Self::Add(destination(d)?, operand(lhs)?, operand(rhs)?),
+            Token::Mult(d, lhs, rhs) => Self::Mult(destination(d)?, operand(lhs)?, operand(rhs)?),
+            Token::Mod(d, lhs, rhs) => Self::Mod(destination(d)?, operand(lhs)?, operand(rhs)?),
+            Token::And(d, lhs, rhs) => Self::And(destination(d)?, operand(lhs)?, operand(rhs)?),
+            Token::Or(d, lhs, rhs) => Self::Or(destination(d)?, operand(lhs)?, operand(rhs)?),
+            Token::Not(d, value) => Self::Not(destination(d)?, operand(value)?),
+            Token::Rmem(d, source) => Self::Rmem(destination(d)?, operand(source)?),
+            Token::Wmem(d, value) => Self::Wmem(operand(d)?, operand(value)?),
+            Token::Noop => Self::Noop,
+            _ => return None,
+        })
+    }
+}
+
+/// A run of instructions that always execute one after another.
+#[derive(Debug)]
+struct Block {
+    /// Each op with the address of its instruction
+    ops: Vec<(usize, Op)>,
+
+    /// Address of the instruction after the last op
+    end: usize,
+}
+
+/// Blocks compiled for the `Blocks` engine, by start address.
+///
+/// A block is every instruction from its start up to the first one that
+/// [`Op::compile`] rejects, which the interpreter then runs before the next
+/// block. Writing to any address covered by a block drops every block, so
+/// self-modifying code is compiled again.
+#[derive(Debug, Default)]
+pub struct BlockCache {
+    blocks: Vec<Option<Block>>,
+
+    /// Whether each address is part of a compiled block
+    compiled: Vec<bool>,
+
+    /// Set when a write drops the blocks, so the running block stops
+    invalidated: bool,
+}
+
+impl BlockCache {
+    /// Drop every block if `address` is part of one.
+    pub fn invalidate(&mut self, address: usize) {
+        if self.compiled.get(address) == Some(&true) {
+            self.blocks.clear();
+            self.compiled.clear();
+            self.invalidated = true;
+        }
+    }
+
+    fn take(&mut self, start: usize) -> Option<Block> {
+        self.blocks.get_mut(start)?.take()
+    }
+
+    fn insert(&mut self, start: usize, block: Block) {
+        if self.blocks.is_empty() {
+            self.blocks.resize_with(REGISTER_OFFSET as usize, || None);
+        }
+
+        self.blocks[start] = Some(block);
+    }
+
+    fn compile(&mut self, memory: &[u16], start: usize) -> Block {
+        let mut ops = Vec::new();
+        let mut pc = start;
+
+        while let Some(token) = memory
+            .get(pc..REGISTER_OFFSET as usize)
+            .and_then(Token::parse)
+        {
+            let Some(op) = Op::compile(token) else {
+                break;
+            };
+
+            // Registers can't be invalidated, so no block may reach them
+            if pc + token.pc_delta() > REGISTER_OFFSET as usize {
+                break;
+            }
+
+            ops.push((pc, op));
+            pc += token.pc_delta();
+        }
+
+        if self.compiled.is_empty() {
+            self.compiled.resize(REGISTER_OFFSET as usize, false);
+        }
+        self.compiled[start..pc].fill(true);
+
+        Block { ops, end: pc }
+    }
+}
+
+impl Machine {
+    /// Run the block starting at pc, compiling it first if needed, leaving pc
+    /// at the instruction after it. Stops early before an op that can't run,
+    /// such as a pop from an empty stack, so the interpreter can report it,
+    /// and after a write to compiled code.
+    pub(crate) fn run_block(&mut self) {
+        let start = self.pc;
+        if start >= REGISTER_OFFSET as usize {
+            return;
+        }
+
+        let block = match self.blocks.take(start) {
+            Some(block) => block,
+            None => self.blocks.compile(&self.memory, start),
+        };
+
+        self.blocks.invalidated = false;
+        self.pc = block.end;
+
+        for (i, (pc, op)) in block.ops.iter().enumerate() {
+            if !self.execute(*op) {
+                self.pc = *pc;
+                break;
+            }
+
+            if self.blocks.invalidated {
+                self.pc = block.ops.get(i + 1).map_or(block.end, |(pc, _)| *pc);
+                break;
+            }
+        }
+
+        if !self.blocks.invalidated {
+            self.blocks.insert(start, block);
+        }
+    }
+
+    /// Execute an op the way `process_token` would, returning false without
+    /// changing anything if the interpreter would stop with an error on it.
+    fn execute(&mut self, op: Op) -> bool {
+        match op {
+            Op::Set(d, value) => self.write(d, self.operand(value)),
+
+            Op::Push(value) => self.stack.push(self.operand(value)),
+
+            Op::Pop(d) => match self.stack.pop() {
+                Some(value) => self.write(d, value),
+                None => return false,
+            },
+
+            Op::Eq(d, lhs, rhs) => {
+                let result = self.operand(lhs) == self.operand(rhs);
+                self.write(d, result as u16);
+            }
+
+            Op::Gt(d, lhs, rhs) => {
+                let result = self.operand(lhs) > self.operand(rhs);
+                self.write(d, result as u16);
+            }
+
+            Op::Add(d, lhs, rhs) => {
+                let result = (self.operand(lhs) as u32 + self.operand(rhs) as u32) % U15_MAX as u32;
+                self.write(d, result as u16);
+            }
+
+            Op::Mult(d, lhs, rhs) => {
+                let result = (self.operand(lhs) as u32 * self.operand(rhs) as u32) % U15_MAX as u32;
+                self.write(d, result as u16);
+            }
+
+            Op::Mod(d, lhs, rhs) => match self.operand(rhs) {
+                0 => return false,
+                rhs => self.write(d, self.operand(lhs) % rhs),
+            },
+
+            Op::And(d, lhs, rhs) => {
+                let result = (self.operand(lhs) & self.operand(rhs)) % U15_MAX;
+                self.write(d, result);
+            }
+
+            Op::Or(d, lhs, rhs) => {
+                let result = (self.operand(lhs) | self.operand(rhs)) % U15_MAX;
+                self.write(d, result);
+            }
+
+            Op::Not(d, value) => {
+                let result = (!self.operand(value)) % U15_MAX;
+                self.write(d, result);
+            }
+
+            Op::Rmem(d, source) => match self.memory.get(self.operand(source) as usize) {
+                Some(&value) => self.write(d, value),
+                None => return false,
+            },
+
+            Op::Wmem(d, value) => {
+                let d = self.operand(d) as usize;
+                if d >= self.memory.len() {
+                    return false;
+                }
+
+                self.write(d, self.operand(value));
+            }
+
+            Op::Noop => {}
+        }
+
+        true
+    }
+
+    fn operand(&self, operand: Operand) -> u16 {
+        match operand {
+            Operand::Literal(value) => value,
+            Operand::Register(address) => self.memory[address],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::loader;
+    use crate::machine::{Engine, RunState};
+
+    use super::*;
+
+    #[test]
+    fn test_engines_agree() {
+        let program = loader::load(Path::new("challenge.bin"), None).unwrap();
+
+        let mut reference = Machine::new(program.clone()).with_engine(Engine::Cached);
+        let mut blocks = Machine::new(program).with_engine(Engine::Blocks);
+
+        let mut commands = [
+            "take tablet",
+            "use tablet",
+            "doorway",
+            "north",
+            "north",
+            "bridge",
+            "continue",
+            "down",
+            "east",
+            "take empty lantern",
+            "west",
+            "west",
+            "passage",
+            "ladder",
+        ]
+        .into_iter();
+
+        // Compare the two every time they stop
+        loop {
+            let run_state = reference.run().clone();
+
+            assert_eq!(*blocks.run(), run_state);
+            assert_eq!(blocks.pc, reference.pc);
+            assert_eq!(blocks.stack, reference.stack);
+            assert!(blocks.memory == reference.memory);
+
+            match run_state {
+                RunState::InuptNeeded => match commands.next() {
+                    Some(command) => {
+                        reference.push_input(&format!("{command}\n"));
+                        blocks.push_input(&format!("{command}\n"));
+                    }
+                    None => break,
+                },
+
+                RunState::Halt | RunState::Error(_) => panic!("{run_state:?}"),
+
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+/// Where a running program reads its input from, a line at a time.
+pub trait InputSource {
+    /// Read the next line of input including its newline, or `None` once the
+    /// input has run out.
+    fn read_line(&mut self) -> io::Result<Option<String>>;
+}
+
+/// Where a running program writes its output to.
+pub trait OutputSink {
+    fn write(&mut self, output: &str) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: InputSource + ?Sized> InputSource for &mut T {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        (**self).read_line()
+    }
+}
+
+impl<T: InputSource + ?Sized> InputSource for Box<T> {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        (**self).read_line()
+    }
+}
+
+impl<T: OutputSink + ?Sized> OutputSink for &mut T {
+    fn write(&mut self, output: &str) -> io::Result<()> {
+        (**self).write(output)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+impl<T: OutputSink + ?Sized> OutputSink for Box<T> {
+    fn write(&mut self, output: &str) -> io::Result<()> {
+        (**self).write(output)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+/// Read a line from `reader`, adding a newline to a last line without one.
+fn read_line_from(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+
+    if !line.ends_with('\n') {
+        line.push('\n');
+    }
+
+    Ok(Some(line))
+}
+
+pub struct StdinSource;
+
+impl InputSource for StdinSource {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        read_line_from(&mut io::stdin().lock())
+    }
+}
+
+pub struct FileSource {
+    reader: BufReader<File>,
+}
+
+impl FileSource {
+    pub fn open(file_path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(File::open(file_path)?),
+        })
+    }
+}
+
+impl InputSource for FileSource {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        read_line_from(&mut self.reader)
+    }
+}
+
+/// Input given up front, for tests and scripted runs.
+#[derive(Clone, Debug, Default)]
+pub struct StringSource {
+    lines: VecDeque<String>,
+}
+
+impl StringSource {
+    pub fn new(input: &str) -> Self {
+        Self {
+            lines: input.lines().map(|line| format!("{line}\n")).collect(),
+        }
+    }
+
+    pub fn push_line(&mut self, line: &str) {
+        self.lines
+            .push_back(format!("{}\n", line.trim_end_matches('\n')));
+    }
+}
+
+impl InputSource for StringSource {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        Ok(self.lines.pop_front())
+    }
+}
+
+pub struct StdoutSink;
+
+impl OutputSink for StdoutSink {
+    fn write(&mut self, output: &str) -> io::Result<()> {
+        io::stdout().write_all(output.as_bytes())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+pub struct FileSink {
+    writer: BufWriter<File>,
+}
+
+impl FileSink {
+    pub fn create(file_path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(file_path)?),
+        })
+    }
+}
+
+impl OutputSink for FileSink {
+    fn write(&mut self, output: &str) -> io::Result<()> {
+        self.writer.write_all(output.as_bytes())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Collects output in memory.
+#[derive(Clone, Debug, Default)]
+pub struct StringSink {
+    contents: String,
+}
+
+impl StringSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contents(&self) -> &str {
+        &self.contents
+    }
+
+    /// Take everything written so far, leaving the sink empty.
+    pub fn take(&mut self) -> String {
+        std::mem::take(&mut self.contents)
+    }
+}
+
+impl OutputSink for StringSink {
+    fn write(&mut self, output: &str) -> io::Result<()> {
+        self.contents += output;
+        Ok(())
+    }
+}
+
+/// Writes the same output to every one of several sinks.
+#[derive(Default)]
+pub struct TeeSink<'a> {
+    sinks: Vec<Box<dyn OutputSink + 'a>>,
+}
+
+impl<'a> TeeSink<'a> {
+    pub fn new() -> Self {
+        Self { sinks: Vec::new() }
+    }
+
+    pub fn with(mut self, sink: impl OutputSink + 'a) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+}
+
+impl OutputSink for TeeSink<'_> {
+    fn write(&mut self, output: &str) -> io::Result<()> {
+        self.sinks
+            .iter_mut()
+            .try_for_each(|sink| sink.write(output))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sinks.iter_mut().try_for_each(|sink| sink.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{Machine, RunState, REGISTER_OFFSET};
+    use crate::parse::{IN, JMP, OUT};
+
+    #[test]
+    fn test_run_with() {
+        // Echo every character of input, twice
+        #[rustfmt::skip]
+        let program = vec![
+            IN, REGISTER_OFFSET,
+            OUT, REGISTER_OFFSET,
+            OUT, REGISTER_OFFSET,
+            JMP, 0,
+        ];
+
+        let mut input = StringSource::new("ab\nc");
+        let mut first = StringSink::new();
+        let mut second = StringSink::new();
+        let mut output = TeeSink::new().with(&mut first).with(&mut second);
+
+        let mut machine = Machine::new(program);
+        let run_state = machine.run_with(&mut input, &mut output).unwrap();
+
+        assert_eq!(*run_state, RunState::InuptNeeded);
+        drop(output);
+        assert_eq!(first.contents(), "aabb\n\ncc\n\n");
+        assert_eq!(second.contents(), first.contents());
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+
+use crate::machine::{State, REGISTER_OFFSET, U15_MAX};
+use crate::project::Project;
+
+/// A run of consecutive words of main memory that all changed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub address: usize,
+    pub old: Vec<u16>,
+    pub new: Vec<u16>,
+}
+
+/// Everything that differs between two states of a machine.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diff {
+    pub pc: Option<(usize, usize)>,
+
+    /// Each changed register with its old and new value
+    pub registers: Vec<(usize, u16, u16)>,
+
+    pub stack: Option<(Vec<u16>, Vec<u16>)>,
+    pub memory: Vec<Change>,
+}
+
+impl Diff {
+    pub fn new(before: &State, after: &State) -> Self {
+        let mut diff = Self {
+            pc: (before.pc != after.pc).then_some((before.pc, after.pc)),
+            stack: (before.stack != after.stack)
+                .then(|| (before.stack.clone(), after.stack.clone())),
+            ..Self::default()
+        };
+
+        let main_memory = U15_MAX as usize;
+        let words = before.memory.iter().zip(&after.memory).enumerate();
+
+        for (address, (old, new)) in words {
+            if old == new {
+                continue;
+            }
+
+            if address >= main_memory {
+                diff.registers.push((address - main_memory, *old, *new));
+                continue;
+            }
+
+            match diff.memory.last_mut() {
+                Some(change) if change.address + change.old.len() == address => {
+                    change.old.push(*old);
+                    change.new.push(*new);
+                }
+                _ => diff.memory.push(Change {
+                    address,
+                    old: vec![*old],
+                    new: vec![*new],
+                }),
+            }
+        }
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Show the diff with the names `project` gives to addresses.
+    pub fn display<'a>(&'a self, project: &'a Project) -> impl fmt::Display + 'a {
+        DiffDisplay {
+            diff: self,
+            project,
+        }
+    }
+}
+
+struct DiffDisplay<'a> {
+    diff: &'a Diff,
+    project: &'a Project,
+}
+
+impl fmt::Display for DiffDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Diff {
+            pc,
+            registers,
+            stack,
+            memory,
+        } = self.diff;
+
+        if let Some((old, new)) = pc {
+            writeln!(f, "pc: {old} -> {new}")?;
+        }
+
+        for (register, old, new) in registers {
+            write!(f, "r{register}")?;
+            if let Some(name) = self.project.name(REGISTER_OFFSET as usize + register) {
+                write!(f, " {name}")?;
+            }
+            writeln!(f, ": {old} -> {new}")?;
+        }
+
+        if let Some((old, new)) = stack {
+            writeln!(f, "stack: {old:?} -> {new:?}")?;
+        }
+
+        for change in memory {
+            let end = change.address + change.old.len();
+            if change.old.len() == 1 {
+                write!(f, "{}", change.address)?;
+            } else {
+                write!(f, "{}..{end}", change.address)?;
+            }
+
+            let names: Vec<&str> = (change.address..end)
+                .filter_map(|address| self.project.name(address))
+                .collect();
+            if !names.is_empty() {
+                write!(f, " {}", names.join(", "))?;
+            }
+
+            writeln!(f, ": {} -> {}", words(&change.old), words(&change.new))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn words(words: &[u16]) -> String {
+    words
+        .iter()
+        .map(|word| word.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::NUM_REGISTERS;
+
+    #[test]
+    fn test_diff() {
+        let before = State {
+            pc: 10,
+            memory: vec![0; (U15_MAX + NUM_REGISTERS) as usize],
+            stack: vec![1],
+        };
+
+        let mut after = before.clone();
+        after.pc = 12;
+        after.memory[5] = 1;
+        after.memory[6] = 2;
+        after.memory[8] = 3;
+        after.memory[REGISTER_OFFSET as usize + 7] = 4;
+
+        let diff = Diff::new(&before, &after);
+        assert_eq!(
+            diff.memory,
+            [
+                Change {
+                    address: 5,
+                    old: vec![0, 0],
+                    new: vec![1, 2],
+                },
+                Change {
+                    address: 8,
+                    old: vec![0],
+                    new: vec![3],
+                },
+            ]
+        );
+
+        let project = Project::parse("room = 6\nlocation = 8\nteleporter = 32775\n").unwrap();
+        assert_eq!(
+            diff.display(&project).to_string(),
+            "pc: 10 -> 12\n\
+             r7 teleporter: 0 -> 4\n\
+             5..7 room: 0 0 -> 1 2\n\
+             8 location: 0 -> 3\n"
+        );
+
+        assert!(Diff::new(&before, &before).is_empty());
+    }
+}
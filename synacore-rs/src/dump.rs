@@ -0,0 +1,157 @@
+use std::io;
+
+use anyhow::bail;
+
+use crate::device::{InputSource, OutputSink};
+use crate::events::Event;
+use crate::machine::Machine;
+use crate::parse::parse_word;
+
+/// When to stop a machine to dump its memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DumpPoint {
+    /// The program halts or fails
+    Halt,
+
+    /// The program first asks for input that isn't available
+    Input,
+
+    /// The program asks for input once the input given has run out
+    InputEnd,
+
+    /// Execution reaches this address
+    Breakpoint(usize),
+
+    /// This many instructions have been executed
+    Steps(u64),
+}
+
+impl DumpPoint {
+    /// Parse `halt`, `input`, `input-end`, `break=ADDRESS` or `steps=N`.
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        Ok(match input.split_once('=') {
+            None if input == "halt" => Self::Halt,
+            None if input == "input" => Self::Input,
+            None if input == "input-end" => Self::InputEnd,
+            Some(("break", address)) => Self::Breakpoint(parse_word(address)? as usize),
+            Some(("steps", steps)) => Self::Steps(steps.parse()?),
+            _ => bail!("expected halt, input, input-end, break=ADDRESS or steps=N: {input}"),
+        })
+    }
+}
+
+/// Run a machine until it reaches `point`, reading input from `input` when
+/// the point is not `Input`. Returns false if the program stopped, or the
+/// input ran out, first.
+pub fn run_to(
+    machine: &mut Machine,
+    point: DumpPoint,
+    mut input: impl InputSource,
+    mut output: impl OutputSink,
+) -> io::Result<bool> {
+    // Steps are counted after they run, so these points are never reached
+    // once running
+    if point == DumpPoint::Breakpoint(machine.pc()) || point == DumpPoint::Steps(0) {
+        return Ok(true);
+    }
+
+    let steps = matches!(point, DumpPoint::Breakpoint(_) | DumpPoint::Steps(_));
+    let mut events = machine.events();
+    if steps {
+        events = events.with_steps();
+    }
+    let input_handle = events.input();
+
+    let mut executed = 0;
+
+    while let Some(event) = events.next() {
+        match event {
+            Event::Step { .. } => {
+                executed += 1;
+
+                let reached = match point {
+                    DumpPoint::Breakpoint(address) => events.machine().pc() == address,
+                    DumpPoint::Steps(steps) => executed == steps,
+                    _ => false,
+                };
+
+                if reached {
+                    output.flush()?;
+                    return Ok(true);
+                }
+            }
+
+            Event::Output(s) => output.write(&s)?,
+
+            Event::NeedInput => {
+                if point == DumpPoint::Input {
+                    output.flush()?;
+                    return Ok(true);
+                }
+
+                match input.read_line()? {
+                    Some(line) => input_handle.push(&line),
+                    None => {
+                        output.flush()?;
+                        return Ok(point == DumpPoint::InputEnd);
+                    }
+                }
+            }
+
+            Event::Halted | Event::Fault(_) => {
+                output.flush()?;
+                return Ok(point == DumpPoint::Halt);
+            }
+        }
+    }
+
+    output.flush()?;
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{StringSink, StringSource};
+    use crate::machine::REGISTER_OFFSET;
+    use crate::parse::{HALT, IN, NOOP, OUT};
+
+    #[test]
+    fn test_run_to() {
+        #[rustfmt::skip]
+        let program = vec![
+            NOOP,
+            IN, REGISTER_OFFSET,
+            OUT, REGISTER_OFFSET,
+            HALT,
+        ];
+
+        let run_with = |point, input| {
+            let mut machine = Machine::new(program.clone());
+            let mut output = StringSink::new();
+            let reached =
+                run_to(&mut machine, point, StringSource::new(input), &mut output).unwrap();
+
+            (reached, machine.pc(), output.take())
+        };
+        let run = |point| run_with(point, "a");
+
+        assert_eq!(run(DumpPoint::Input), (true, 1, String::new()));
+        assert_eq!(run_with(DumpPoint::InputEnd, ""), (true, 1, String::new()));
+        assert_eq!(run(DumpPoint::InputEnd), (false, 5, "a".to_string()));
+        assert_eq!(run(DumpPoint::Halt), (true, 5, "a".to_string()));
+        assert_eq!(run(DumpPoint::Breakpoint(3)), (true, 3, String::new()));
+        assert_eq!(run(DumpPoint::Steps(0)), (true, 0, String::new()));
+        assert_eq!(run(DumpPoint::Steps(1)), (true, 1, String::new()));
+        assert_eq!(run(DumpPoint::Breakpoint(2)), (false, 5, "a".to_string()));
+
+        assert_eq!(
+            DumpPoint::parse("break=0x10").unwrap(),
+            DumpPoint::Breakpoint(16)
+        );
+        assert_eq!(DumpPoint::parse("steps=5").unwrap(), DumpPoint::Steps(5));
+        assert_eq!(DumpPoint::parse("input-end").unwrap(), DumpPoint::InputEnd);
+        assert!(DumpPoint::parse("later").is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use crate::machine::{Machine, RunState};
+use crate::parse::Token;
+
+/// Something that happened while running a program.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The program printed this text
+    Output(String),
+
+    /// The program is waiting for input, push some through the input handle
+    /// before asking for the next event
+    NeedInput,
+
+    /// The program halted
+    Halted,
+
+    /// The program failed
+    Fault(String),
+
+    /// The instruction at `pc` was executed, only yielded with `with_steps`
+    Step { pc: usize, token: Token },
+}
+
+/// Feeds input to a running machine. Clones share the same queue, so input
+/// can be pushed from wherever is convenient.
+#[derive(Clone, Debug, Default)]
+pub struct InputHandle {
+    queue: Rc<RefCell<VecDeque<String>>>,
+}
+
+impl InputHandle {
+    pub fn push(&self, input: &str) {
+        self.queue.borrow_mut().push_back(input.to_string());
+    }
+}
+
+/// Runs a machine as an iterator of events. The iterator ends after
+/// `Halted` or `Fault`.
+///
+/// ```
+/// # use synacore_rs::events::Event;
+/// # use synacore_rs::parse::{HALT, IN};
+/// # use synacore_rs::{Machine, REGISTER_OFFSET};
+/// # let mut machine = Machine::new(vec![IN, REGISTER_OFFSET, HALT]);
+/// let events = machine.events();
+/// let input = events.input();
+///
+/// for event in events {
+///     match event {
+///         Event::Output(s) => print!("{s}"),
+///         Event::NeedInput => input.push("look\n"),
+///         _ => {}
+///     }
+/// }
+/// ```
+pub struct Events<'a> {
+    machine: &'a mut Machine,
+    input: InputHandle,
+    steps: bool,
+    finished: bool,
+}
+
+impl Machine {
+    pub fn events(&mut self) -> Events<'_> {
+        Events {
+            machine: self,
+            input: InputHandle::default(),
+            steps: false,
+            finished: false,
+        }
+    }
+}
+
+impl Events<'_> {
+    /// Also yield a `Step` for every instruction executed.
+    pub fn with_steps(mut self) -> Self {
+        self.steps = true;
+        self
+    }
+
+    pub fn input(&self) -> InputHandle {
+        self.input.clone()
+    }
+
+    pub fn machine(&self) -> &Machine {
+        self.machine
+    }
+}
+
+impl Iterator for Events<'_> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        if self.finished {
+            return None;
+        }
+
+        for input in self.input.queue.borrow_mut().drain(..) {
+            self.machine.push_input(&input);
+        }
+
+        loop {
+            let pc = self.machine.pc();
+            let (token, run_state) = if self.steps {
+                let token = self.machine.memory().get(pc..).and_then(Token::parse);
+                (token, self.machine.run_once())
+            } else {
+                (None, self.machine.run())
+            };
+
+            match run_state {
+                RunState::Continue => {
+                    if let Some(token) = token {
+                        return Some(Event::Step { pc, token });
+                    }
+                }
+
+                RunState::BufferedOutput(s) => return Some(Event::Output(s.clone())),
+
+                RunState::InuptNeeded => return Some(Event::NeedInput),
+
+                RunState::Halt => {
+                    self.finished = true;
+                    return Some(Event::Halted);
+                }
+
+                RunState::Error(e) => {
+                    self.finished = true;
+                    return Some(Event::Fault(e.clone()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::REGISTER_OFFSET;
+    use crate::parse::{HALT, IN, OUT};
+
+    #[test]
+    fn test_events() {
+        #[rustfmt::skip]
+        let program = vec![
+            OUT, '>' as u16,
+            IN, REGISTER_OFFSET,
+            OUT, REGISTER_OFFSET,
+            HALT,
+        ];
+
+        let mut machine = Machine::new(program.clone());
+        let events = machine.events();
+        let input = events.input();
+
+        let mut seen = Vec::new();
+        for event in events {
+            if event == Event::NeedInput {
+                input.push("a");
+            }
+            seen.push(event);
+        }
+
+        assert_eq!(
+            seen,
+            [
+                Event::Output(">".to_string()),
+                Event::NeedInput,
+                Event::Output("a".to_string()),
+                Event::Halted,
+            ]
+        );
+
+        let mut machine = Machine::new(program);
+        let mut events = machine.events().with_steps();
+        assert_eq!(
+            events.next(),
+            Some(Event::Step {
+                pc: 0,
+                token: Token::Out('>' as u16)
+            })
+        );
+        assert_eq!(events.next(), Some(Event::Output(">".to_string())));
+        assert_eq!(events.next(), Some(Event::NeedInput));
+    }
+}
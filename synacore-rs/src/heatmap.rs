@@ -0,0 +1,200 @@
+use std::fmt::Write;
+
+use crate::machine::{Machine, U15_MAX};
+use crate::parse::Token;
+
+/// Width of a heatmap image in pixels, one per word, so that the image of
+/// main memory is 256 by 128.
+pub const IMAGE_WIDTH: usize = 256;
+
+/// How many times a word of main memory was accessed each way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    /// Read by `rmem`
+    pub reads: u64,
+
+    /// Written by any instruction
+    pub writes: u64,
+
+    /// Executed as part of an instruction
+    pub executions: u64,
+}
+
+impl Counts {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Counts the accesses to each word of main memory over a run, to map out
+/// where the code, the data and the code the program decrypts are.
+///
+/// Registers and the stack aren't part of main memory, so accesses to them
+/// aren't counted. Enable it with [`Machine::with_heatmap`], which slows the
+/// `Blocks` engine down as described on [`Machine::run`].
+#[derive(Clone, Debug)]
+pub struct Heatmap {
+    counts: Vec<Counts>,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self {
+            counts: vec![Counts::default(); U15_MAX as usize],
+        }
+    }
+}
+
+impl Heatmap {
+    /// The counts of every address of main memory, by address.
+    pub fn counts(&self) -> &[Counts] {
+        &self.counts
+    }
+
+    /// `address,reads,writes,executions` for every address that was
+    /// accessed, with a header line.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("address,reads,writes,executions\n");
+
+        for (address, counts) in self.counts.iter().enumerate() {
+            if !counts.is_empty() {
+                let _ = writeln!(
+                    csv,
+                    "{address},{},{},{}",
+                    counts.reads, counts.writes, counts.executions
+                );
+            }
+        }
+
+        csv
+    }
+
+    /// A binary PPM image of main memory, one pixel per word in rows of
+    /// [`IMAGE_WIDTH`]. Reads are green, writes red and executions blue, each
+    /// on a log scale up to the most any word had, so words that are only
+    /// executed are blue and decrypted code is magenta.
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let height = self.counts.len() / IMAGE_WIDTH;
+        let mut image = format!("P6\n{IMAGE_WIDTH} {height}\n255\n").into_bytes();
+
+        let max = |count: fn(&Counts) -> u64| self.counts.iter().map(count).max().unwrap_or(0);
+        let max_reads = max(|counts| counts.reads);
+        let max_writes = max(|counts| counts.writes);
+        let max_executions = max(|counts| counts.executions);
+
+        for counts in &self.counts {
+            image.extend([
+                intensity(counts.writes, max_writes),
+                intensity(counts.reads, max_reads),
+                intensity(counts.executions, max_executions),
+            ]);
+        }
+
+        image
+    }
+}
+
+/// Scale `count` to a colour channel, logarithmically so that words
+/// accessed a few times still show up next to ones accessed millions of
+/// times. Anything accessed at all is at least 64.
+fn intensity(count: u64, max: u64) -> u8 {
+    if count == 0 {
+        return 0;
+    }
+
+    let scale = (count as f64).ln_1p() / (max as f64).ln_1p();
+    64 + (scale * 191.0).round() as u8
+}
+
+impl Machine {
+    /// Count the accesses to main memory from here on, see [`Heatmap`].
+    pub fn with_heatmap(mut self) -> Self {
+        self.heatmap = Some(Box::default());
+        self
+    }
+
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_deref()
+    }
+
+    /// Count the words of the instruction at pc as executed.
+    pub(crate) fn heatmap_execute(&mut self, token: Token) {
+        let Some(heatmap) = &mut self.heatmap else {
+            return;
+        };
+
+        let words = self.pc..(self.pc + token.pc_delta()).min(U15_MAX as usize);
+        for counts in &mut heatmap.counts[words] {
+            counts.executions += 1;
+        }
+    }
+
+    /// Count a read of `address` by `rmem`.
+    pub(crate) fn heatmap_read(&mut self, address: usize) {
+        if let Some(counts) = self.heatmap_counts(address) {
+            counts.reads += 1;
+        }
+    }
+
+    /// Count a write of `address`.
+    pub(crate) fn heatmap_write(&mut self, address: usize) {
+        if let Some(counts) = self.heatmap_counts(address) {
+            counts.writes += 1;
+        }
+    }
+
+    fn heatmap_counts(&mut self, address: usize) -> Option<&mut Counts> {
+        self.heatmap.as_mut()?.counts.get_mut(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{RunState, REGISTER_OFFSET};
+    use crate::parse::{HALT, RMEM, WMEM};
+
+    #[test]
+    fn test_heatmap() {
+        #[rustfmt::skip]
+        let program = vec![
+            RMEM, REGISTER_OFFSET, 9,
+            WMEM, 10, 7,
+            WMEM, 10, REGISTER_OFFSET,
+            HALT,
+        ];
+
+        let mut machine = Machine::new(program).with_heatmap();
+        assert_eq!(*machine.run(), RunState::Halt);
+
+        let counts = machine.heatmap().unwrap().counts();
+        assert_eq!(
+            counts[0],
+            Counts {
+                reads: 0,
+                writes: 0,
+                executions: 1,
+            }
+        );
+        assert_eq!(counts[9].reads, 1);
+        assert_eq!(counts[9].executions, 1);
+        assert_eq!(counts[10].writes, 2);
+        assert!(counts[11].is_empty());
+
+        let heatmap = machine.heatmap().unwrap();
+        assert_eq!(
+            heatmap.to_csv().lines().take(3).collect::<Vec<_>>(),
+            ["address,reads,writes,executions", "0,0,0,1", "1,0,0,1"]
+        );
+
+        let ppm = heatmap.to_ppm();
+        let header = b"P6\n256 128\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        assert_eq!(ppm.len(), header.len() + 256 * 128 * 3);
+
+        let pixel = |address: usize| &ppm[header.len() + address * 3..][..3];
+        assert_eq!(pixel(9), [0, 255, 255]);
+        assert_eq!(pixel(10), [255, 0, 0]);
+        assert_eq!(pixel(11), [0, 0, 0]);
+    }
+}
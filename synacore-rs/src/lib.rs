@@ -0,0 +1,53 @@
+//! A virtual machine for the [Synacor Challenge](https://challenge.synacor.com/)
+//! architecture, along with the tools built around it: program loaders and
+//! patches, recorded replays of play sessions, and replay minimization.
+//!
+//! The machine itself is in [`machine`]. It can be stepped directly, run as an
+//! iterator of [`events::Event`]s, or run against an [`device::InputSource`]
+//! and [`device::OutputSink`]:
+//!
+//! ```
+//! use synacore_rs::device::{StringSink, StringSource};
+//! use synacore_rs::parse::{HALT, IN, OUT};
+//! use synacore_rs::{Machine, RunState, REGISTER_OFFSET};
+//!
+//! // Echo one character of input, then halt
+//! let program = vec![IN, REGISTER_OFFSET, OUT, REGISTER_OFFSET, HALT];
+//!
+//! let mut machine = Machine::new(program);
+//! let mut output = StringSink::new();
+//! let run_state = machine
+//!     .run_with(StringSource::new("x"), &mut output)
+//!     .unwrap();
+//!
+//! assert_eq!(*run_state, RunState::Halt);
+//! assert_eq!(output.contents(), "x");
+//! ```
+//!
+//! Nothing in the library sets up logging, it only emits [`log`] records.
+//! Errors are returned as [`anyhow::Error`] or [`std::io::Error`] rather than
+//! panicking.
+
+pub mod block;
+pub mod device;
+pub mod diff;
+pub mod dump;
+pub mod events;
+pub mod heatmap;
+pub mod loader;
+pub mod machine;
+pub mod meta;
+pub mod minimize;
+pub mod monitor;
+pub mod parse;
+pub mod patch;
+pub mod project;
+pub mod replay;
+pub mod scan;
+pub mod session;
+pub mod strings;
+pub mod transcript;
+pub mod translate;
+
+pub use machine::{Engine, Machine, RunState, State, NUM_REGISTERS, REGISTER_OFFSET, U15_MAX};
+pub use parse::Token;
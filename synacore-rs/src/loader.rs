@@ -0,0 +1,316 @@
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::machine::{NUM_REGISTERS, U15_MAX};
+use crate::parse::parse_16_bit_little_endian;
+
+/// Most words a program can have, enough to fill main memory and the
+/// registers.
+const MAX_LEN: usize = (U15_MAX + NUM_REGISTERS) as usize;
+
+/// Number of words per line when writing a hex dump.
+const HEX_DUMP_WIDTH: usize = 8;
+
+/// On disk representations of a program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Raw 16 bit little endian words, like `challenge.bin`
+    LittleEndian,
+
+    /// Raw 16 bit big endian words
+    BigEndian,
+
+    /// Decimal bytes separated by commas and/or newlines, like `program`
+    ByteText,
+
+    /// Decimal words separated by commas and/or newlines, like `program-u16`
+    WordText,
+
+    /// Lines of `address: word word ...` in hex. Anything after a `|` is
+    /// ignored, so an ascii column may follow the words, as it does when
+    /// exporting.
+    HexDump,
+}
+
+impl Format {
+    pub const ALL: [Self; 5] = [
+        Self::LittleEndian,
+        Self::BigEndian,
+        Self::ByteText,
+        Self::WordText,
+        Self::HexDump,
+    ];
+
+    /// Guess the format of a program file from its contents.
+    ///
+    /// Text is told apart from binary by its character set. Decimal text
+    /// containing any value above 255 must be words, otherwise it is taken to
+    /// be bytes. Binaries are read as little endian unless big endian gives
+    /// more words with a zero high byte, which opcodes and ascii text do.
+    pub fn detect(contents: &[u8]) -> Self {
+        let is_decimal_text = contents
+            .iter()
+            .all(|b| b.is_ascii_digit() || b.is_ascii_whitespace() || *b == b',');
+
+        if is_decimal_text {
+            let is_words = decimal_values(contents).any(|v| v.is_ok_and(|v| v > 0xff));
+
+            return if is_words {
+                Self::WordText
+            } else {
+                Self::ByteText
+            };
+        }
+
+        let first_line = contents
+            .split(|b| *b == b'\n')
+            .map(|line| line.trim_ascii())
+            .find(|line| !line.is_empty());
+
+        if let Some(line) = first_line {
+            if let Some(colon) = line.iter().position(|b| *b == b':') {
+                if colon > 0 && line[..colon].iter().all(u8::is_ascii_hexdigit) {
+                    return Self::HexDump;
+                }
+            }
+        }
+
+        let small_words = |high_byte: usize| {
+            contents
+                .chunks_exact(2)
+                .filter(|chunk| chunk[high_byte] == 0)
+                .count()
+        };
+
+        if small_words(0) > small_words(1) {
+            Self::BigEndian
+        } else {
+            Self::LittleEndian
+        }
+    }
+}
+
+/// Read a program from disk, detecting its format when none is given.
+pub fn load(file_path: &Path, format: Option<Format>) -> anyhow::Result<Vec<u16>> {
+    let contents = fs::read(file_path)
+        .with_context(|| format!("Could not read file {}", file_path.display()))?;
+
+    let format = format.unwrap_or_else(|| Format::detect(&contents));
+
+    parse(&contents, format)
+        .with_context(|| format!("Could not load {} as {format:?}", file_path.display()))
+}
+
+/// Parse a program, failing if it is too long to fit in memory.
+pub fn parse(contents: &[u8], format: Format) -> anyhow::Result<Vec<u16>> {
+    let program = match format {
+        Format::LittleEndian => {
+            if !contents.len().is_multiple_of(2) {
+                bail!("odd number of bytes: {}", contents.len());
+            }
+
+            parse_16_bit_little_endian(contents)
+        }
+
+        Format::BigEndian => {
+            if !contents.len().is_multiple_of(2) {
+                bail!("odd number of bytes: {}", contents.len());
+            }
+
+            contents
+                .chunks(2)
+                .map(|chunk| (chunk[0] as u16) << 8 | chunk[1] as u16)
+                .collect()
+        }
+
+        Format::ByteText => {
+            let bytes = decimal_values(contents)
+                .map(|v| {
+                    let v = v?;
+                    u8::try_from(v).map_err(|_| anyhow!("byte out of range: {v}"))
+                })
+                .collect::<anyhow::Result<Vec<u8>>>()?;
+
+            return parse(&bytes, Format::LittleEndian);
+        }
+
+        Format::WordText => decimal_values(contents)
+            .map(|v| {
+                let v = v?;
+                u16::try_from(v).map_err(|_| anyhow!("word out of range: {v}"))
+            })
+            .collect::<anyhow::Result<Vec<u16>>>()?,
+
+        Format::HexDump => parse_hex_dump(contents)?,
+    };
+
+    if program.len() > MAX_LEN {
+        bail!(
+            "program too long: {} words, at most {MAX_LEN} fit",
+            program.len()
+        );
+    }
+
+    Ok(program)
+}
+
+/// Write a program to disk in the given format.
+pub fn save(program: &[u16], file_path: &Path, format: Format) -> anyhow::Result<()> {
+    fs::write(file_path, export(program, format))
+        .with_context(|| format!("Could not write file {}", file_path.display()))
+}
+
+pub fn export(program: &[u16], format: Format) -> Vec<u8> {
+    match format {
+        Format::LittleEndian => program.iter().flat_map(|w| w.to_le_bytes()).collect(),
+
+        Format::BigEndian => program.iter().flat_map(|w| w.to_be_bytes()).collect(),
+
+        Format::ByteText => program
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .map(|b| format!("{b},\n"))
+            .collect::<String>()
+            .into_bytes(),
+
+        Format::WordText => program
+            .iter()
+            .map(|w| format!("{w},\n"))
+            .collect::<String>()
+            .into_bytes(),
+
+        Format::HexDump => {
+            let mut output = String::new();
+
+            for (line_num, line) in program.chunks(HEX_DUMP_WIDTH).enumerate() {
+                let _ = write!(output, "{:04x}:", line_num * HEX_DUMP_WIDTH);
+                for word in line {
+                    let _ = write!(output, " {word:04x}");
+                }
+
+                let padding = 5 * (HEX_DUMP_WIDTH - line.len());
+                let ascii: String = line.iter().map(|word| ascii_char(*word)).collect();
+                let _ = writeln!(output, "{:padding$}  |{ascii}|", "");
+            }
+
+            output.into_bytes()
+        }
+    }
+}
+
+/// How a word is shown in the ascii column of a hex dump.
+fn ascii_char(word: u16) -> char {
+    match u8::try_from(word) {
+        Ok(b) if b.is_ascii_graphic() || b == b' ' => b as char,
+        _ => '.',
+    }
+}
+
+fn decimal_values(contents: &[u8]) -> impl Iterator<Item = anyhow::Result<u32>> + '_ {
+    contents
+        .split(|b| *b == b',' || b.is_ascii_whitespace())
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            let v = std::str::from_utf8(v)?;
+            v.parse::<u32>()
+                .with_context(|| format!("invalid number: {v}"))
+        })
+}
+
+fn parse_hex_dump(contents: &[u8]) -> anyhow::Result<Vec<u16>> {
+    let contents = std::str::from_utf8(contents)?;
+
+    let mut program = Vec::new();
+
+    for (line_num, line) in contents.lines().enumerate() {
+        let line = match line.split_once('|') {
+            Some((line, _ascii)) => line,
+            None => line,
+        }
+        .trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let (address, words) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("line {}: missing address", line_num + 1))?;
+
+        let address = usize::from_str_radix(address.trim(), 16)
+            .with_context(|| format!("line {}: invalid address {address}", line_num + 1))?;
+
+        for (i, word) in words.split_whitespace().enumerate() {
+            let word = u16::from_str_radix(word, 16)
+                .with_context(|| format!("line {}: invalid word {word}", line_num + 1))?;
+
+            if address + i >= MAX_LEN {
+                bail!(
+                    "line {}: address out of bounds: {:x}",
+                    line_num + 1,
+                    address + i
+                );
+            }
+
+            if program.len() <= address + i {
+                program.resize(address + i + 1, 0);
+            }
+
+            program[address + i] = word;
+        }
+    }
+
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: [u16; 9] = [21, 19, 'H' as u16, 19, 'i' as u16, 9, 32768, 32769, 300];
+
+    #[test]
+    fn test_round_trip() {
+        for format in &Format::ALL {
+            let exported = export(&PROGRAM, *format);
+
+            assert_eq!(parse(&exported, *format).unwrap(), PROGRAM, "{format:?}");
+            assert_eq!(Format::detect(&exported), *format, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_detect_repo_text_formats() {
+        assert_eq!(Format::detect(b"21,\n0,\n19,\n0,\n"), Format::ByteText);
+        assert_eq!(Format::detect(b"21,\n19,\n32768,\n"), Format::WordText);
+    }
+
+    #[test]
+    fn test_hex_dump_ascii_column_and_gaps() {
+        let program = parse(b"0000: 0015 0013 |..|\n0004: 0001\n", Format::HexDump).unwrap();
+
+        assert_eq!(program, [0x15, 0x13, 0, 0, 1]);
+
+        assert_eq!(
+            String::from_utf8(export(&PROGRAM, Format::HexDump)).unwrap(),
+            "0000: 0015 0013 0048 0013 0069 0009 8000 8001  |..H.i...|\n\
+             0008: 012c                                     |.|\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(parse(b"1,2,3", Format::LittleEndian).is_err());
+        assert!(parse(b"256,0", Format::ByteText).is_err());
+        assert!(parse(b"70000", Format::WordText).is_err());
+
+        let full = export(&vec![0; MAX_LEN], Format::WordText);
+        assert!(parse(&full, Format::WordText).is_ok());
+        let too_long = export(&vec![0; MAX_LEN + 1], Format::WordText);
+        assert!(parse(&too_long, Format::WordText).is_err());
+        assert!(parse(b"7fffffff: 0015\n", Format::HexDump).is_err());
+    }
+}
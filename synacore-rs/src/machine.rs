@@ -1,17 +1,28 @@
 use anyhow::{anyhow, Context};
-use log::{debug, trace};
+use log::trace;
 use std::{
     collections::VecDeque,
+    io,
     ops::{Add, Mul},
 };
 
+use crate::block::BlockCache;
+use crate::device::{InputSource, OutputSink};
+use crate::heatmap::Heatmap;
+use crate::monitor::Monitor;
 use crate::parse::Token;
 
-const U15_MAX: u16 = 32768;
-const REGISTER_OFFSET: u16 = U15_MAX;
-const NUM_REGISTERS: u16 = 8;
+/// Size of main memory, and the modulus of all arithmetic.
+pub const U15_MAX: u16 = 32768;
+/// Values from here up name registers rather than literals.
+pub const REGISTER_OFFSET: u16 = U15_MAX;
+pub const NUM_REGISTERS: u16 = 8;
 
-#[derive(Debug, PartialEq)]
+/// Words in the longest instruction, an opcode and three arguments.
+const MAX_INSTRUCTION_LEN: usize = 4;
+
+/// Why the machine stopped running.
+#[derive(Clone, Debug, PartialEq)]
 pub enum RunState {
     Continue,
     BufferedOutput(String),
@@ -20,92 +31,319 @@ pub enum RunState {
     Halt,
 }
 
+/// Everything that decides what a program does next, apart from its input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct State {
+    pub pc: usize,
+
+    /// Main memory followed by the registers
+    pub memory: Vec<u16>,
+
+    pub stack: Vec<u16>,
+}
+
+/// How the machine executes instructions. All engines behave identically.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Decode every instruction each time it runs
+    Uncached,
+
+    /// Decode every instruction once, and cache it until its code is written
+    #[default]
+    Cached,
+
+    /// Compile straight-line runs of instructions into blocks with their
+    /// arguments resolved, which run without going back to the decoder
+    Blocks,
+}
+
+impl Engine {
+    pub const ALL: [Self; 3] = [Self::Uncached, Self::Cached, Self::Blocks];
+}
+
+/// A Synacor virtual machine. Registers are stored in memory directly after
+/// main memory, at `REGISTER_OFFSET`, so an address names either.
+///
+/// Instructions are decoded once and cached by address. Writing to memory
+/// drops the cached instructions the written word is part of, so
+/// self-modifying code is decoded again before it runs.
 pub struct Machine {
     run_state: RunState,
-    pc: usize,
-    stack: Vec<u16>,
-    memory: Vec<u16>,
+    pub(crate) pc: usize,
+    pub(crate) stack: Vec<u16>,
+    pub(crate) memory: Vec<u16>,
     input_buffer: VecDeque<char>,
-    output_buffer: Vec<char>,
+    pub(crate) output_buffer: Vec<char>,
+    decoded: Vec<Option<Token>>,
+    pub(crate) blocks: BlockCache,
+    engine: Engine,
+    pub(crate) monitor: Option<Box<Monitor>>,
+    pub(crate) heatmap: Option<Box<Heatmap>>,
+}
+
+// Clones start with empty decode and block caches, which are rebuilt as they
+// run, and without a monitor or heatmap, so that the many clones kept for undo stay small
+impl Clone for Machine {
+    fn clone(&self) -> Self {
+        Self {
+            run_state: self.run_state.clone(),
+            pc: self.pc,
+            stack: self.stack.clone(),
+            memory: self.memory.clone(),
+            input_buffer: self.input_buffer.clone(),
+            output_buffer: self.output_buffer.clone(),
+            decoded: Vec::new(),
+            blocks: BlockCache::default(),
+            engine: self.engine,
+            monitor: None,
+            heatmap: None,
+        }
+    }
 }
 
 impl Machine {
+    /// Load `program` at address 0, with everything after it zeroed. A
+    /// program too long to fit in memory and the registers is cut short, and
+    /// the machine starts out stopped with an error.
     pub fn new(program: Vec<u16>) -> Self {
-        let mut memory = program.clone();
-        memory.extend(vec![0; (U15_MAX + NUM_REGISTERS) as usize - memory.len()].iter());
+        let len = (U15_MAX + NUM_REGISTERS) as usize;
+
+        let run_state = if program.len() > len {
+            RunState::Error(format!(
+                "program too long: {} words, at most {len} fit",
+                program.len()
+            ))
+        } else {
+            RunState::Continue
+        };
+
+        let mut memory = program;
+        memory.resize(len, 0);
 
         Self {
-            run_state: RunState::Continue,
+            run_state,
             pc: 0,
             stack: vec![],
             memory,
             input_buffer: VecDeque::with_capacity(256),
             output_buffer: Vec::with_capacity(512),
+            decoded: Vec::new(),
+            blocks: BlockCache::default(),
+            engine: Engine::default(),
+            monitor: None,
+            heatmap: None,
         }
     }
 
+    /// Make a machine that carries on from `state`.
+    pub fn from_state(state: State) -> Self {
+        let mut machine = Self::new(state.memory);
+        machine.pc = state.pc;
+        machine.stack = state.stack;
+        machine
+    }
+
+    pub fn state(&self) -> State {
+        State {
+            pc: self.pc,
+            memory: self.memory.clone(),
+            stack: self.stack.clone(),
+        }
+    }
+
+    pub fn into_state(self) -> State {
+        State {
+            pc: self.pc,
+            memory: self.memory,
+            stack: self.stack,
+        }
+    }
+
+    pub fn with_engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self.decoded.clear();
+        self.blocks = BlockCache::default();
+        self
+    }
+
+    /// Run until the machine stops with anything other than `Continue`.
+    ///
+    /// Blocks don't report what they access, so while a monitor or heatmap
+    /// is enabled the `Blocks` engine runs like the `Cached` one.
     pub fn run(&mut self) -> &RunState {
-        while *self.run_once() == RunState::Continue {}
+        if !self.resume() {
+            return &self.run_state;
+        }
+
+        if self.engine == Engine::Blocks && self.monitor.is_none() && self.heatmap.is_none() {
+            loop {
+                // Blocks never print, so they have to wait for buffered
+                // output to be handed back first
+                if self.output_buffer.is_empty() {
+                    self.run_block();
+                }
+
+                if !self.step() {
+                    break;
+                }
+            }
+        } else {
+            while self.step() {}
+        }
 
         &self.run_state
     }
 
+    /// Run until the program halts, fails, or needs input after `input` has
+    /// run out, reading input from `input` and writing output to `output`.
+    pub fn run_with(
+        &mut self,
+        mut input: impl InputSource,
+        mut output: impl OutputSink,
+    ) -> io::Result<&RunState> {
+        loop {
+            match self.run().clone() {
+                RunState::Continue => {}
+
+                RunState::BufferedOutput(s) => output.write(&s)?,
+
+                RunState::InuptNeeded => {
+                    output.flush()?;
+
+                    match input.read_line()? {
+                        Some(line) => self.push_input(&line),
+                        None => break,
+                    }
+                }
+
+                RunState::Halt | RunState::Error(_) => break,
+            }
+        }
+
+        output.flush()?;
+
+        Ok(&self.run_state)
+    }
+
+    /// Execute a single instruction, or hand back any buffered output when
+    /// the next instruction is not an `out`.
     pub fn run_once(&mut self) -> &RunState {
+        if self.resume() {
+            self.step();
+        }
+
+        &self.run_state
+    }
+
+    /// Get ready to run after the last stop, returning false if the machine
+    /// can't run yet or ever again.
+    fn resume(&mut self) -> bool {
         match self.run_state {
-            RunState::Halt | RunState::Error(_) => {
-                return &self.run_state;
-            }
+            RunState::Halt | RunState::Error(_) => false,
 
-            RunState::InuptNeeded => {
-                if self.input_buffer.len() == 0 {
-                    return &self.run_state;
-                }
+            RunState::InuptNeeded if self.input_buffer.is_empty() => false,
 
+            RunState::InuptNeeded | RunState::BufferedOutput(_) => {
                 self.run_state = RunState::Continue;
+                true
             }
 
-            RunState::BufferedOutput(_) => {
-                self.run_state = RunState::Continue;
-            }
+            RunState::Continue => true,
+        }
+    }
 
-            RunState::Continue => {}
+    /// Execute the instruction at pc, returning true if the machine can
+    /// carry straight on to the next one.
+    fn step(&mut self) -> bool {
+        let Some(token) = self.fetch() else {
+            self.run_state = RunState::Error(format!(
+                "could not parse instruction at {}: {:?}",
+                self.pc,
+                self.memory.get(self.pc)
+            ));
+            return false;
         };
 
-        debug!("pc: {}", self.pc);
-        debug!("instruction: {:?}", self.memory.get(self.pc));
+        if !matches!(token, Token::Out(_)) && !self.output_buffer.is_empty() {
+            self.run_state = RunState::BufferedOutput(self.flush_output_buffer());
+            return false;
+        }
 
-        if let Some(token) = Token::parse(&self.memory[self.pc..]) {
-            match token {
-                Token::Out(_) => {}
-                _ => {
-                    if self.output_buffer.len() > 0 {
-                        self.run_state = RunState::BufferedOutput(self.flush_output_buffer());
-                        return &self.run_state;
-                    }
-                }
+        if self.monitor.is_some() {
+            self.monitor_execute(token);
+        }
+        if self.heatmap.is_some() {
+            self.heatmap_execute(token);
+        }
+
+        if let Err(e) = self.process_token(token) {
+            self.run_state =
+                RunState::Error(format!("Error processing token: {e}, pc: {}", self.pc));
+        };
+
+        self.run_state == RunState::Continue
+    }
+
+    /// Decode the instruction at pc, from the cache if it has been decoded
+    /// before.
+    fn fetch(&mut self) -> Option<Token> {
+        if let Some(Some(token)) = self.decoded.get(self.pc) {
+            return Some(*token);
+        }
+
+        let token = Token::parse(self.memory.get(self.pc..)?)?;
+
+        // Code running from the registers is never cached, as register
+        // writes don't invalidate anything
+        if self.engine != Engine::Uncached && self.pc < REGISTER_OFFSET as usize {
+            if self.decoded.is_empty() {
+                self.decoded.resize(REGISTER_OFFSET as usize, None);
             }
 
-            // dbg!(&token);
+            self.decoded[self.pc] = Some(token);
+        }
 
-            if let Err(e) = self.process_token(token) {
-                self.run_state =
-                    RunState::Error(format!("Error processing token: {e}, pc: {}", self.pc));
-            };
-        } else {
-            self.run_state = RunState::Error(format!(
-                "could not parse instruction at {}: {}",
-                self.pc, self.memory[self.pc]
-            ));
+        Some(token)
+    }
+
+    /// Write a word to memory or a register, dropping any cached instruction
+    /// or block that includes it.
+    pub(crate) fn write(&mut self, address: usize, value: u16) {
+        self.memory[address] = value;
+
+        if address < self.decoded.len() {
+            let first = address.saturating_sub(MAX_INSTRUCTION_LEN - 1);
+            self.decoded[first..=address].fill(None);
         }
 
-        return &self.run_state;
+        self.blocks.invalidate(address);
+        if self.monitor.is_some() {
+            self.monitor_write(address);
+        }
+        if self.heatmap.is_some() {
+            self.heatmap_write(address);
+        }
     }
 
+    /// Queue characters for `in` instructions to read.
     pub fn push_input(&mut self, input: &str) {
         self.input_buffer.extend(input.chars());
     }
 
+    /// Remove and return input that has been pushed but not read yet.
+    pub fn take_input(&mut self) -> String {
+        self.input_buffer.drain(..).collect()
+    }
+
     fn process_token(&mut self, token: Token) -> anyhow::Result<()> {
+        if let Some(arg) = token
+            .args()
+            .into_iter()
+            .find(|arg| *arg >= REGISTER_OFFSET + NUM_REGISTERS)
+        {
+            return Err(anyhow!("argument out of bounds: {arg}, token: {token:?}"));
+        }
+
         match token {
             Token::Halt => {
                 self.run_state = RunState::Halt;
@@ -113,8 +351,8 @@ impl Machine {
 
             Token::Set(register, value) => {
                 // dbg!(&token);
-                if register >= REGISTER_OFFSET && register < REGISTER_OFFSET + NUM_REGISTERS {
-                    self.memory[register as usize] = self.fetch_val(value);
+                if (REGISTER_OFFSET..REGISTER_OFFSET + NUM_REGISTERS).contains(&register) {
+                    self.write(register as usize, self.fetch_val(value));
 
                     self.pc += token.pc_delta();
                 } else {
@@ -135,7 +373,7 @@ impl Machine {
                 // dbg!(&token);
                 if let Some(value) = self.stack.pop() {
                     // dbg!(value);
-                    self.memory[destination as usize] = value;
+                    self.write(destination as usize, value);
 
                     self.pc += token.pc_delta();
                 } else {
@@ -145,18 +383,10 @@ impl Machine {
 
             Token::Eq(destination, lhs, rhs) => {
                 // dbg!(&token);
-                debug!("Eq: {destination}, {lhs}, {rhs}");
-                debug!("    pc: {}", self.pc);
-                debug!("    lhs is {}", self.fetch_val(lhs));
-                debug!("    rhs is {}", self.fetch_val(rhs));
-
                 if self.fetch_val(lhs) == self.fetch_val(rhs) {
-                    debug!("    lhs == rhs, set {destination} to 1");
-
-                    self.memory[destination as usize] = 1
+                    self.write(destination as usize, 1);
                 } else {
-                    debug!("    lhs != rhs, set {destination} to 0");
-                    self.memory[destination as usize] = 0
+                    self.write(destination as usize, 0);
                 }
 
                 self.pc += token.pc_delta();
@@ -165,9 +395,9 @@ impl Machine {
             Token::Gt(destination, lhs, rhs) => {
                 // dbg!(&token);
                 if self.fetch_val(lhs) > self.fetch_val(rhs) {
-                    self.memory[destination as usize] = 1
+                    self.write(destination as usize, 1);
                 } else {
-                    self.memory[destination as usize] = 0
+                    self.write(destination as usize, 0);
                 }
                 self.pc += token.pc_delta();
             }
@@ -179,34 +409,18 @@ impl Machine {
 
             Token::Jt(test_val, destination) => {
                 // dbg!(&token);
-                debug!("Jt: {test_val}, {destination}");
-                debug!("    pc: {}", self.pc);
-                debug!("    test value is {}", self.fetch_val(test_val));
-
                 if self.fetch_val(test_val) != 0 {
-                    debug!("    test value is non-zero, set pc to {destination}");
-
                     self.pc = self.fetch_val(destination) as usize;
                 } else {
-                    debug!("    test value is zero, continue");
-
                     self.pc += token.pc_delta();
                 }
             }
 
             Token::Jf(test_val, destination) => {
                 // dbg!(&token);
-                debug!("Jf: {test_val}, {destination}");
-                debug!("    pc: {}", self.pc);
-                debug!("    test value is {}", self.fetch_val(test_val));
-
                 if self.fetch_val(test_val) == 0 {
-                    debug!("    test value is zero, set pc to {destination}");
-
                     self.pc = self.fetch_val(destination) as usize;
                 } else {
-                    debug!("    test value is non-zero, continue");
-
                     self.pc += token.pc_delta();
                 }
             }
@@ -215,7 +429,7 @@ impl Machine {
                 // dbg!(&token);
                 let result =
                     Self::aritmatic_mod_u15(self.fetch_val(lhs), self.fetch_val(rhs), u32::add);
-                self.memory[destination as usize] = result;
+                self.write(destination as usize, result);
 
                 self.pc += token.pc_delta();
             }
@@ -224,15 +438,20 @@ impl Machine {
                 // dbg!(&token);
                 let result =
                     Self::aritmatic_mod_u15(self.fetch_val(lhs), self.fetch_val(rhs), u32::mul);
-                self.memory[destination as usize] = result;
+                self.write(destination as usize, result);
 
                 self.pc += token.pc_delta();
             }
 
             Token::Mod(destination, lhs, rhs) => {
                 // dbg!(&token);
-                let result = self.fetch_val(lhs) % self.fetch_val(rhs);
-                self.memory[destination as usize] = result;
+                let rhs = self.fetch_val(rhs);
+                if rhs == 0 {
+                    return Err(anyhow!("Mod: division by zero, token: {token:?}"));
+                }
+
+                let result = self.fetch_val(lhs) % rhs;
+                self.write(destination as usize, result);
 
                 self.pc += token.pc_delta();
             }
@@ -240,7 +459,7 @@ impl Machine {
             Token::And(destination, lhs, rhs) => {
                 // dbg!(&token);
                 let result = (self.fetch_val(lhs) & self.fetch_val(rhs)) % U15_MAX;
-                self.memory[destination as usize] = result;
+                self.write(destination as usize, result);
 
                 self.pc += token.pc_delta();
             }
@@ -248,61 +467,51 @@ impl Machine {
             Token::Or(destination, lhs, rhs) => {
                 // dbg!(&token);
                 let result = (self.fetch_val(lhs) | self.fetch_val(rhs)) % U15_MAX;
-                self.memory[destination as usize] = result;
+                self.write(destination as usize, result);
 
                 self.pc += token.pc_delta();
             }
 
             Token::Not(destination, value) => {
                 // dbg!(&token);
-                debug!("Not: {destination}, {value}");
-                debug!("    pc: {}", self.pc);
-                debug!("    value is {}", self.fetch_val(value));
-
                 let result = (!self.fetch_val(value)) % U15_MAX;
-                debug!("    result is {result}");
 
-                debug!("    set {destination} to {result}");
-                self.memory[destination as usize] = result;
+                self.write(destination as usize, result);
 
                 self.pc += token.pc_delta();
             }
 
             Token::Rmem(destination, source) => {
                 // dbg!(&token);
-                debug!("Rmem: {destination}, {source}");
-                debug!("    pc: {}", self.pc);
-
                 let source = self.fetch_val(source);
 
-                let value = self.memory[source as usize];
-                debug!("    value: {value}");
+                let value = *self
+                    .memory
+                    .get(source as usize)
+                    .with_context(|| format!("Rmem: address out of bounds: {source}"))?;
+                if self.heatmap.is_some() {
+                    self.heatmap_read(source as usize);
+                }
 
-                debug!("    writing {value} to memory address {destination}");
-                self.memory[destination as usize] = value;
+                self.write(destination as usize, value);
 
                 self.pc += token.pc_delta();
             }
 
             Token::Wmem(destination, value) => {
                 // dbg!(&token);
-                debug!("Wmem: {destination}, {value}");
-                debug!("    pc: {}", self.pc);
-
                 let destination = self.fetch_val(destination);
-                debug!("    writing {value} to memory address {destination}");
-                self.memory[destination as usize] = self.fetch_val(value);
+                if destination as usize >= self.memory.len() {
+                    return Err(anyhow!("Wmem: address out of bounds: {destination}"));
+                }
+
+                self.write(destination as usize, self.fetch_val(value));
 
                 self.pc += token.pc_delta();
             }
 
             Token::Call(destination) => {
                 // dbg!(&token);
-                debug!("Call: {destination}");
-                debug!("    pc: {}", self.pc);
-                debug!("    push {} on to the stack", self.pc + token.pc_delta());
-                debug!("    set pc to {destination}");
-
                 self.stack.push(self.pc as u16 + token.pc_delta() as u16);
 
                 self.pc = self.fetch_val(destination) as usize;
@@ -330,7 +539,7 @@ impl Machine {
             Token::In(destination) => {
                 // dbg!(&token);
                 if let Some(ch) = self.input_buffer.pop_front() {
-                    self.memory[destination as usize] = ch as u16;
+                    self.write(destination as usize, ch as u16);
                     self.pc += token.pc_delta();
                 } else {
                     self.run_state = RunState::InuptNeeded;
@@ -339,13 +548,11 @@ impl Machine {
 
             Token::Noop => {
                 // dbg!(&token);
-
                 self.pc += token.pc_delta();
             }
 
             Token::Unknown(_val) => {
                 // dbg!(&token);
-
                 return Err(anyhow!(
                     "process_token: Unknown token encountered at {}: {token:?}",
                     self.pc
@@ -360,14 +567,46 @@ impl Machine {
         self.output_buffer.drain(0..).collect::<String>()
     }
 
-    #[allow(dead_code)]
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn memory(&self) -> &[u16] {
+        &self.memory
+    }
+
     pub fn registers(&self) -> &[u16] {
         &self.memory[REGISTER_OFFSET as usize..(REGISTER_OFFSET + NUM_REGISTERS) as usize]
     }
 
+    pub fn set_register(&mut self, register: u16, value: u16) -> anyhow::Result<()> {
+        if register >= NUM_REGISTERS {
+            return Err(anyhow!("register out of bounds: {register}"));
+        }
+
+        self.write((REGISTER_OFFSET + register) as usize, value);
+
+        Ok(())
+    }
+
+    /// Write a word of main memory, not including registers.
+    pub fn write_memory(&mut self, address: u16, value: u16) -> anyhow::Result<()> {
+        if address >= U15_MAX {
+            return Err(anyhow!("address out of bounds: {address}"));
+        }
+
+        self.write(address as usize, value);
+
+        Ok(())
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
     /// If arg is a register address return the contents of that register,
     /// otherwise return arg
-    fn fetch_val(&self, arg: u16) -> u16 {
+    pub(crate) fn fetch_val(&self, arg: u16) -> u16 {
         if arg < REGISTER_OFFSET {
             arg
         } else {
@@ -382,13 +621,43 @@ impl Machine {
 
 #[cfg(test)]
 mod tests {
-    use crate::parse::{
-        ADD, AND, CALL, EQ, GT, HALT, IN, JF, JMP, JT, MOD, MULT, NOOP, NOT, OR, OUT, POP, PUSH,
-        RET, RMEM, SET, WMEM,
-    };
+    use crate::parse::{ADD, HALT, JMP, JT, MOD, NOOP, OUT, POP, PUSH, RMEM, SET, WMEM};
 
     use super::*;
 
+    #[test]
+    fn test_errors_instead_of_panics() {
+        let run = |program: Vec<u16>| {
+            let mut machine = Machine::new(program);
+            machine.run().clone()
+        };
+
+        assert!(matches!(
+            run(vec![MOD, REGISTER_OFFSET, 1, 0]),
+            RunState::Error(_)
+        ));
+        assert!(matches!(run(vec![PUSH, 1, POP, 40000]), RunState::Error(_)));
+        assert!(matches!(
+            run(vec![
+                SET,
+                REGISTER_OFFSET,
+                40000,
+                RMEM,
+                REGISTER_OFFSET + 1,
+                REGISTER_OFFSET
+            ]),
+            RunState::Error(_)
+        ));
+        assert!(matches!(
+            run(vec![SET, REGISTER_OFFSET, 40000, WMEM, REGISTER_OFFSET, 1]),
+            RunState::Error(_)
+        ));
+
+        let mut machine = Machine::new(vec![0; 40000]);
+        assert!(matches!(machine.run(), RunState::Error(_)));
+        assert_eq!(machine.memory().len(), (U15_MAX + NUM_REGISTERS) as usize);
+    }
+
     #[test]
     fn test_simple_program() {
         #[rustfmt::skip]
@@ -654,4 +923,32 @@ mod tests {
         assert_eq!(machine.registers(), [0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(machine.memory.get(32768 + 8), None);
     }
+
+    #[test]
+    fn test_self_modifying() {
+        #[rustfmt::skip]
+        let program = vec![
+            // 0: print the character stored at 1
+            OUT, 'a' as u16,
+            // 2: halt on the second pass
+            JT, REGISTER_OFFSET, 13,
+            SET, REGISTER_OFFSET, 1,
+            // 8: change the character printed, after it has been decoded
+            WMEM, 1, 'b' as u16,
+            JMP, 0,
+            HALT,
+        ];
+
+        for engine in [Engine::Uncached, Engine::Cached, Engine::Blocks] {
+            let mut machine = Machine::new(program.clone()).with_engine(engine);
+            let mut output = String::new();
+
+            while let RunState::BufferedOutput(s) = machine.run() {
+                output += s;
+            }
+
+            assert_eq!(*machine.run(), RunState::Halt);
+            assert_eq!(output, "ab");
+        }
+    }
 }
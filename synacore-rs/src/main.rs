@@ -1,30 +1,320 @@
 use std::{
-    fs::{self, File},
-    io::{self, BufRead, BufReader}, collections::VecDeque,
+    io::{self, IsTerminal},
+    path::Path,
+    time::{Duration, Instant},
 };
 
-use clap::Parser;
-use log::{debug, error};
-use parse::parse_16_bit_little_endian;
+use clap::{Parser, Subcommand, ValueEnum};
+use log::{debug, error, info, warn};
+use time::{format_description::well_known::Rfc3339, macros::format_description, OffsetDateTime};
 
-use machine::{Machine, RunState};
-use replay::{ReplayManager, REPLAY_SAVE_DIR};
-
-mod machine;
-mod parse;
-mod replay;
+use synacore_rs::device::{
+    FileSink, FileSource, InputSource, OutputSink, StdinSource, StdoutSink, StringSink,
+    StringSource,
+};
+use synacore_rs::diff::Diff;
+use synacore_rs::dump::{self, DumpPoint};
+use synacore_rs::loader::{self, Format};
+use synacore_rs::machine::{Engine, Machine, RunState, U15_MAX};
+use synacore_rs::minimize::{Goal, Minimizer};
+use synacore_rs::parse;
+use synacore_rs::patch::Patch;
+use synacore_rs::project::{Project, PROJECT_FILE};
+use synacore_rs::replay::{
+    self, Entry, ReplayFormat, ReplayHeader, ReplayManager, MAIN_BRANCH, REPLAY_SAVE_DIR,
+};
+use synacore_rs::session::{Handled, Session, Stop};
+use synacore_rs::strings;
+use synacore_rs::transcript;
+use synacore_rs::translate;
 
 /// Simple program to greet a person
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to program to run
     #[arg(short, long, default_value = "challenge.bin")]
     program: String,
 
+    /// Format of the program file, detected from its contents if not given
+    #[arg(short, long, value_enum)]
+    format: Option<FormatArg>,
+
     /// Instead of running program print a decompiled version
     #[arg(short, long, default_value_t = false)]
     decompile: bool,
+
+    /// Patch file to apply to the program before running it, may be repeated
+    #[arg(long)]
+    patch: Vec<String>,
+
+    /// Instead of running program write it to this path, after applying any patches
+    #[arg(long)]
+    export: Option<String>,
+
+    /// Format to export the program in
+    #[arg(long, value_enum, default_value = "little-endian")]
+    export_format: FormatArg,
+
+    /// Replay to play back, defaults to the most recent numbered replay
+    #[arg(short, long, conflicts_with = "no_replay")]
+    replay: Option<String>,
+
+    /// Start without playing back any replay
+    #[arg(long, default_value_t = false)]
+    no_replay: bool,
+
+    /// Play the whole replay without waiting for input, then exit
+    #[arg(short, long, default_value_t = false)]
+    autoplay: bool,
+
+    /// With --autoplay, keep playing interactively once the replay runs out
+    #[arg(short, long, default_value_t = false, requires = "autoplay")]
+    interactive: bool,
+
+    /// Read commands from this file instead of stdin, implies --batch
+    #[arg(long, global = true)]
+    input: Option<String>,
+
+    /// Read commands without prompting, echoing each one to the output, and
+    /// save the replay once the input runs out. On by default when stdin is
+    /// not a terminal
+    #[arg(long, default_value_t = false)]
+    batch: bool,
+
+    /// Write program output to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Branch of the replay to play back
+    #[arg(short, long, default_value = MAIN_BRANCH)]
+    branch: String,
+
+    /// Save this session as a new branch of the replay it played, forking
+    /// where the session stopped following the replay
+    #[arg(long, conflicts_with = "no_replay")]
+    save_branch: Option<String>,
+
+    /// Stop playing back the replay at the checkpoint with this name
+    #[arg(short, long)]
+    until: Option<String>,
+
+    /// Author recorded in saved replays, defaults to $USER
+    #[arg(long)]
+    author: Option<String>,
+
+    /// Save replays in the legacy format of one command per line
+    #[arg(long, default_value_t = false)]
+    legacy_replays: bool,
+
+    /// How the machine executes instructions
+    #[arg(long, value_enum, default_value_t)]
+    engine: EngineArg,
+
+    /// Project file of named addresses, created by !def if it doesn't exist
+    #[arg(long, default_value = PROJECT_FILE)]
+    project: String,
+
+    /// Directory replays are read from and saved to
+    #[arg(long, global = true, default_value = REPLAY_SAVE_DIR)]
+    replay_dir: String,
+}
+
+/// The library's [`Format`], as a command line argument.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum FormatArg {
+    /// Raw 16 bit little endian words, like `challenge.bin`
+    LittleEndian,
+
+    /// Raw 16 bit big endian words
+    BigEndian,
+
+    /// Decimal bytes separated by commas and/or newlines, like `program`
+    ByteText,
+
+    /// Decimal words separated by commas and/or newlines, like `program-u16`
+    WordText,
+
+    /// Lines of `address: word word ...` in hex. Anything after a `|` is
+    /// ignored, so an ascii column may follow the words, as it does when
+    /// exporting.
+    HexDump,
+}
+
+impl From<FormatArg> for Format {
+    fn from(format: FormatArg) -> Self {
+        match format {
+            FormatArg::LittleEndian => Self::LittleEndian,
+            FormatArg::BigEndian => Self::BigEndian,
+            FormatArg::ByteText => Self::ByteText,
+            FormatArg::WordText => Self::WordText,
+            FormatArg::HexDump => Self::HexDump,
+        }
+    }
+}
+
+/// The library's [`Engine`], as a command line argument.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum EngineArg {
+    /// Decode every instruction each time it runs
+    Uncached,
+
+    /// Decode every instruction once, and cache it until its code is written
+    #[default]
+    Cached,
+
+    /// Compile straight-line runs of instructions into blocks with their
+    /// arguments resolved, which run without going back to the decoder
+    Blocks,
+}
+
+impl From<EngineArg> for Engine {
+    fn from(engine: EngineArg) -> Self {
+        match engine {
+            EngineArg::Uncached => Self::Uncached,
+            EngineArg::Cached => Self::Cached,
+            EngineArg::Blocks => Self::Blocks,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage saved replays
+    #[command(subcommand)]
+    Replays(ReplaysCommand),
+
+    /// Time running the program up to its first prompt, which includes the
+    /// challenge's self-test, with each engine
+    Bench {
+        /// Number of times to run the program for each measurement
+        #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+        runs: u32,
+    },
+
+    /// Translate the program into a Rust source file that runs it without
+    /// the interpreter, see `synacore_rs::translate`
+    Translate {
+        /// Path to write the Rust source to
+        file: String,
+
+        /// Translate the memory as it is when the program first asks for
+        /// input, including any code it decrypted while starting
+        #[arg(long)]
+        at_prompt: bool,
+    },
+
+    /// Run the program up to its first prompt, then list the regions of code
+    /// it modified
+    SelfMod {
+        /// Write main memory at the first prompt, after the program has
+        /// finished modifying its code, to this path in the export format
+        #[arg(long)]
+        snapshot: Option<String>,
+    },
+
+    /// Run the program to a chosen point and dump its main memory, as a hex
+    /// dump with an ascii column on stdout unless a file is given. Commands
+    /// are read from --input
+    Dump {
+        /// Where to stop: halt, input, input-end, break=ADDRESS or steps=N
+        #[arg(long, value_parser = DumpPoint::parse, default_value = "input")]
+        at: DumpPoint,
+
+        /// Write memory as a little endian binary to this path
+        #[arg(long)]
+        binary: Option<String>,
+
+        /// Write memory as a hex dump to this path
+        #[arg(long)]
+        text: Option<String>,
+    },
+
+    /// Compare two memory images, such as dumps, in any format the program
+    /// can be loaded from
+    Diff { before: String, after: String },
+
+    /// List the strings in the program, or in memory once the program has
+    /// run to a chosen point. Commands are read from --input
+    Strings {
+        /// Run to this point first: halt, input, input-end, break=ADDRESS or
+        /// steps=N
+        #[arg(long, value_parser = DumpPoint::parse)]
+        at: Option<DumpPoint>,
+
+        /// Shortest string to list, in characters
+        #[arg(long, default_value_t = strings::DEFAULT_MIN_LEN)]
+        min_len: usize,
+
+        /// Print a disassembly of all of memory annotated with the strings
+        /// instead
+        #[arg(long)]
+        disassemble: bool,
+    },
+
+    /// Run the program to a chosen point counting the reads, writes and
+    /// executions of each word of main memory, then save them as a heatmap
+    /// image and a CSV. Commands are read from --input
+    Heatmap {
+        /// Where to stop: halt, input, input-end, break=ADDRESS or steps=N
+        #[arg(long, value_parser = DumpPoint::parse, default_value = "input")]
+        at: DumpPoint,
+
+        /// Path to write the image to, as a PPM
+        #[arg(long, default_value = "heatmap.ppm")]
+        image: String,
+
+        /// Path to write the counts of each accessed address to, as a CSV
+        #[arg(long)]
+        csv: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ReplaysCommand {
+    /// List saved replays with their command counts and dates
+    List,
+
+    /// List the branches of a replay
+    Branches { name: String },
+
+    /// Delete a saved replay
+    Delete { name: String },
+
+    /// Rename a saved replay
+    Rename { from: String, to: String },
+
+    /// Play every replay and compare its output to its stored transcript
+    Test {
+        /// Store the current output as the expected transcript
+        #[arg(long, default_value_t = false)]
+        bless: bool,
+    },
+
+    /// Shrink a replay to the fewest commands that still reach a goal
+    #[command(group(clap::ArgGroup::new("goal").required(true)))]
+    Minimize {
+        name: String,
+
+        /// Goal: a command's output contains this text
+        #[arg(long, group = "goal")]
+        contains: Option<String>,
+
+        /// Goal: memory holds a value, given as address=value
+        #[arg(long, group = "goal", value_parser = Goal::parse_memory)]
+        memory: Option<Goal>,
+
+        /// Goal: execution reaches this address
+        #[arg(long, group = "goal", value_parser = Goal::parse_pc)]
+        pc: Option<Goal>,
+
+        /// Name to save the minimized replay as, defaults to the next numbered replay
+        #[arg(long)]
+        save_as: Option<String>,
+    },
 }
 
 fn main() {
@@ -33,80 +323,680 @@ fn main() {
 
     let args = Args::parse();
 
-    let file_path = args.program;
+    let mut replay_manager = ReplayManager::new(Path::new(&args.replay_dir));
 
-    let file_contents = fs::read(&file_path).expect(&format!("Could not read file {file_path}"));
+    let replay_format = if args.legacy_replays {
+        ReplayFormat::Legacy
+    } else {
+        ReplayFormat::Structured
+    };
 
-    let program = parse_16_bit_little_endian(&file_contents);
+    if let Some(Command::Diff { before, after }) = &args.command {
+        diff_images(before, after, &args);
+        return;
+    }
+
+    if let Some(Command::Replays(command)) = &args.command {
+        manage_replays(&replay_manager, command, &args);
+        return;
+    }
+
+    let program = load_program(&args);
 
-    // dbg!(&file_contents);
+    if let Some(Command::Bench { runs }) = args.command {
+        bench(&program, runs);
+        return;
+    }
+
+    if let Some(Command::Translate { file, at_prompt }) = &args.command {
+        let mut machine = Machine::new(program.clone());
+        if *at_prompt {
+            while let RunState::Continue | RunState::BufferedOutput(_) = machine.run() {}
+        }
+
+        std::fs::write(file, translate::translate(&machine.into_state()))
+            .unwrap_or_else(|e| panic!("Could not write {file}: {e}"));
+        info!("Translated program to {file}");
+        return;
+    }
+
+    if let Some(Command::SelfMod { snapshot }) = &args.command {
+        self_mod(&program, snapshot.as_deref(), args.export_format.into());
+        return;
+    }
+
+    if let Some(Command::Dump { at, binary, text }) = &args.command {
+        dump(
+            &program,
+            *at,
+            binary.as_deref(),
+            text.as_deref(),
+            args.input.as_deref(),
+        );
+        return;
+    }
+
+    if let Some(Command::Strings {
+        at,
+        min_len,
+        disassemble,
+    }) = &args.command
+    {
+        list_strings(&program, *at, *min_len, *disassemble, args.input.as_deref());
+        return;
+    }
+
+    if let Some(Command::Heatmap { at, image, csv }) = &args.command {
+        heatmap(&program, *at, image, csv.as_deref(), args.input.as_deref());
+        return;
+    }
+
+    if let Some(export_path) = args.export {
+        loader::save(&program, Path::new(&export_path), args.export_format.into())
+            .unwrap_or_else(|e| panic!("Could not export program: {e:#}"));
+        return;
+    }
 
     if args.decompile {
         println!("{}", parse::decompile(&program));
         return;
     }
 
-    let mut replay_manager = ReplayManager::new();
+    // Only sessions recover journals, so that looking at replays or memory
+    // never changes the replay directory
+    for replay_file_path in replay_manager
+        .recover_journals(replay_format)
+        .expect("Error recovering unfinished replays")
+    {
+        info!("Recovered unfinished replay {}", replay_file_path.display());
+    }
+
+    let replay = if args.no_replay {
+        None
+    } else if args.replay.is_some() {
+        args.replay
+    } else {
+        replay_manager
+            .last_replay()
+            .expect("Error reading replay files")
+    };
+
+    let program_hash = replay::program_hash(&program);
 
-    let mut autoplay_commands = VecDeque::new();
+    let mut autoplay_commands = Vec::new();
 
-    if let Some(last_replay) = ReplayManager::replay_files().expect("Error reading replay files").last() {
-        let replay_file = File::open(format!("{REPLAY_SAVE_DIR}/{last_replay}")).expect(&format!("Error opening replay file {last_replay}"));
-        for line in BufReader::new(replay_file).lines() {
-            autoplay_commands.push_back(line.expect(&format!("Error reading replay file {last_replay}")));        
+    if let Some(replay_name) = &replay {
+        let replay = replay_manager
+            .load(replay_name)
+            .unwrap_or_else(|e| panic!("Error reading replay file {replay_name}: {e}"));
+
+        if let Some(replay_hash) = &replay.header.program_hash {
+            if *replay_hash != program_hash {
+                warn!("Replay {replay_name} was recorded against a different program");
+            }
         }
+
+        let path = replay
+            .path(&args.branch)
+            .unwrap_or_else(|e| panic!("Error reading replay file {replay_name}: {e}"));
+
+        autoplay_commands = path.entries;
+    }
+
+    replay_manager.set_header(ReplayHeader {
+        program_hash: Some(program_hash),
+        created: OffsetDateTime::now_utc().format(&Rfc3339).ok(),
+        author: args.author.or_else(|| std::env::var("USER").ok()),
+    });
+
+    let machine = Machine::new(program).with_engine(args.engine.into());
+    let mut session = Session::new(machine, replay_manager, Path::new(&args.project))
+        .unwrap_or_else(|e| panic!("Could not load project: {e:#}"))
+        .with_autoplay(autoplay_commands, args.until);
+
+    // Sessions that take input are journaled as they go, and the journal is
+    // turned into a replay if the session is interrupted
+    if !args.autoplay || args.interactive {
+        session
+            .journal_on_interrupt(replay_format)
+            .unwrap_or_else(|e| panic!("{e:#}"));
     }
 
-    let mut machine = Machine::new(program);
+    let mut output: Box<dyn OutputSink> = match &args.output {
+        Some(output_path) => Box::new(
+            FileSink::create(Path::new(output_path))
+                .unwrap_or_else(|e| panic!("Could not create output file {output_path}: {e}")),
+        ),
+        None => Box::new(StdoutSink),
+    };
+
+    let batch = args.batch || args.input.is_some() || !io::stdin().is_terminal();
+
+    let mut input: Box<dyn InputSource> = match &args.input {
+        Some(input_path) => Box::new(
+            FileSource::open(Path::new(input_path))
+                .unwrap_or_else(|e| panic!("Could not open input file {input_path}: {e}")),
+        ),
+        None => Box::new(StdinSource),
+    };
+
+    let mut headless = args.autoplay;
+    let mut interactive_after_replay = args.interactive;
 
     debug!("Running program");
 
-    loop {
-        match machine.run() {
-            RunState::Continue => {
-                continue;
-            }
+    'session: while session.run(&mut output).expect("Error writing output") == Stop::NeedInput {
+        // Read lines until one is sent to the program
+        loop {
+            if headless {
+                match session.next_replay_command() {
+                    Some(command) => {
+                        output
+                            .write(&format!("{command}\n"))
+                            .expect("Error writing output");
+                        session
+                            .send(&format!("{command}\n"))
+                            .expect("Error storing input line");
+                        break;
+                    }
+
+                    None if interactive_after_replay => {
+                        headless = false;
+                    }
 
-            RunState::BufferedOutput(s) => {
-                print!("{s}");
+                    None => break 'session,
+                }
             }
 
-            RunState::InuptNeeded => {
-                if let Some(command) = autoplay_commands.front() {
+            output.flush().expect("Error writing output");
+
+            if !batch {
+                if let Some(command) = session.peek_replay_command() {
                     println!("Replay command:{command}");
                 }
+            }
 
-                let mut line = String::new();
-                let stdin = io::stdin();
-                stdin.lock().read_line(&mut line).unwrap();
+            let line = match input.read_line() {
+                Ok(Some(line)) => line,
 
-                if line == "\n" {
-                    if let Some(command) = autoplay_commands.pop_front() {
-                        println!("{command}");
-                        line = format!("{command}\n");
-                    }
+                Ok(None) => {
+                    debug!("End of input");
+                    break 'session;
                 }
 
-                let line = replay_manager.push(line).expect("Error storing input line");
-
-                dbg!(&line);
+                Err(e) => {
+                    error!("Error reading input: {e}");
+                    break 'session;
+                }
+            };
 
-                machine.push_input(&line);
+            if batch {
+                output.write(&line).expect("Error writing output");
             }
 
-            RunState::Halt => {
-                debug!("program execution complete");
-                break;
-            }
+            match session
+                .handle_line(&line)
+                .expect("Error storing input line")
+            {
+                Handled::Sent => break,
 
-            RunState::Error(e) => {
-                error!("{e}");
-                break;
+                Handled::Replayed(command) => {
+                    println!("{command}");
+                    break;
+                }
+
+                Handled::Reply(reply) => print!("{reply}"),
+
+                Handled::Replay => {
+                    headless = true;
+                    interactive_after_replay = true;
+                }
+
+                Handled::Quit => break 'session,
             }
         }
     }
 
+    output.flush().expect("Error writing output");
+
+    let failed = session.failed();
+    let replay_manager = session.into_replay_manager();
+
+    // A replay played back without any new input has nothing new to save
+    if args.autoplay && !args.interactive {
+        replay_manager
+            .discard()
+            .expect("Error removing replay journal");
+
+        if failed {
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
+    if let (Some(branch_name), Some(replay_name)) = (&args.save_branch, &replay) {
+        replay_manager
+            .save_as_branch(replay_name, branch_name, &args.branch)
+            .unwrap_or_else(|e| panic!("Error saving branch {branch_name} of {replay_name}: {e}"));
+
+        return;
+    }
+
+    let replay_file_path = replay_manager
+        .next_file_path()
+        .expect("Error getting replay file path");
+
     replay_manager
-        .save(&ReplayManager::next_file_path().expect("Error getting replay file path"))
+        .save(&replay_file_path, replay_format)
         .unwrap();
 }
+
+/// Print how long the program takes to reach its first prompt with each
+/// engine, checking that they all stop in the same state.
+fn bench(program: &[u16], runs: u32) {
+    let mut baseline: Option<(Duration, Machine)> = None;
+
+    for engine in &Engine::ALL {
+        // Machines are made before and dropped after the timing, so only
+        // running them is timed
+        let mut machines: Vec<Machine> = (0..runs)
+            .map(|_| Machine::new(program.to_vec()).with_engine(*engine))
+            .collect();
+
+        let start = Instant::now();
+        for machine in &mut machines {
+            while let RunState::Continue | RunState::BufferedOutput(_) = machine.run() {}
+        }
+        let elapsed = start.elapsed() / runs;
+
+        let machine = machines.pop().expect("There should be at least one run");
+
+        let speedup = match &baseline {
+            Some((baseline, expected)) => {
+                if machine.pc() != expected.pc() || machine.memory() != expected.memory() {
+                    error!("{engine:?} engine stopped in a different state");
+                }
+
+                baseline.as_secs_f64() / elapsed.as_secs_f64()
+            }
+            None => 1.0,
+        };
+        baseline.get_or_insert((elapsed, machine));
+
+        println!(
+            "{:>10}: {elapsed:>10.2?} per run, {speedup:.2}x",
+            format!("{engine:?}")
+        );
+    }
+}
+
+/// Print the regions of code the program modifies before its first prompt,
+/// with the instructions that wrote them.
+fn self_mod(program: &[u16], snapshot_path: Option<&str>, format: Format) {
+    let mut machine = Machine::new(program.to_vec()).with_monitor();
+    while let RunState::Continue | RunState::BufferedOutput(_) = machine.run() {}
+
+    let monitor = machine.monitor().expect("Monitor should be enabled");
+
+    for region in monitor.regions() {
+        let mut writers: Vec<usize> = region
+            .clone()
+            .filter_map(|address| monitor.writer(address))
+            .collect();
+        writers.sort_unstable();
+        writers.dedup();
+
+        println!(
+            "{:>5}..{:<5} {:>5} words, written by {writers:?}",
+            region.start,
+            region.end,
+            region.len()
+        );
+    }
+
+    let Some(path) = snapshot_path else {
+        return;
+    };
+
+    // Snapshot where the run stopped rather than when modified code first
+    // ran, as the program may still have been writing more of it then
+    if monitor.regions().is_empty() {
+        warn!("No modified code ran, not saving a snapshot");
+        return;
+    }
+
+    loader::save(
+        &machine.memory()[..U15_MAX as usize],
+        Path::new(path),
+        format,
+    )
+    .unwrap_or_else(|e| panic!("Could not save snapshot: {e:#}"));
+    info!("Saved memory from pc {} to {path}", machine.pc());
+}
+
+/// Run the program to `point` and write its main memory out.
+fn dump(
+    program: &[u16],
+    point: DumpPoint,
+    binary_path: Option<&str>,
+    text_path: Option<&str>,
+    input_path: Option<&str>,
+) {
+    let machine = run_to(Machine::new(program.to_vec()), point, input_path);
+    info!(
+        "pc: {}, registers: {:?}, stack: {:?}",
+        machine.pc(),
+        machine.registers(),
+        machine.stack()
+    );
+
+    let memory = &machine.memory()[..U15_MAX as usize];
+
+    if let Some(path) = binary_path {
+        loader::save(memory, Path::new(path), Format::LittleEndian)
+            .unwrap_or_else(|e| panic!("Could not write dump: {e:#}"));
+    }
+
+    match text_path {
+        Some(path) => loader::save(memory, Path::new(path), Format::HexDump)
+            .unwrap_or_else(|e| panic!("Could not write dump: {e:#}")),
+        // Ignore errors so that the dump can be piped to head
+        None if binary_path.is_none() => {
+            let _ =
+                io::Write::write_all(&mut io::stdout(), &loader::export(memory, Format::HexDump));
+        }
+        None => {}
+    }
+}
+
+/// Run the machine to `point`, reading commands from `input_path`.
+fn run_to(mut machine: Machine, point: DumpPoint, input_path: Option<&str>) -> Machine {
+    let input: Box<dyn InputSource> = match input_path {
+        Some(path) => {
+            Box::new(FileSource::open(Path::new(path)).expect("Could not open input file"))
+        }
+        None => Box::new(StringSource::default()),
+    };
+
+    let reached =
+        dump::run_to(&mut machine, point, input, StringSink::new()).expect("Could not read input");
+
+    if !reached {
+        warn!("Program stopped before reaching {point:?}");
+    }
+
+    machine
+}
+
+/// Print the strings in the program's memory, or a disassembly annotated
+/// with them.
+fn list_strings(
+    program: &[u16],
+    point: Option<DumpPoint>,
+    min_len: usize,
+    disassemble: bool,
+    input_path: Option<&str>,
+) {
+    let memory = match point {
+        Some(point) => run_to(Machine::new(program.to_vec()), point, input_path).memory()
+            [..U15_MAX as usize]
+            .to_vec(),
+        None => program.to_vec(),
+    };
+
+    let found = strings::extract(&memory, min_len);
+
+    let output = if disassemble {
+        strings::disassemble(&memory, 0, usize::MAX, &found)
+    } else {
+        found
+            .iter()
+            .map(|found| {
+                format!(
+                    "{:>5} {:<14} {:?}\n",
+                    found.address,
+                    format!("{:?}", found.kind),
+                    found.text
+                )
+            })
+            .collect()
+    };
+
+    // Ignore errors so that the output can be piped to head
+    let _ = io::Write::write_all(&mut io::stdout(), output.as_bytes());
+}
+
+/// Run the program to `point` and save the heatmap of its memory accesses.
+fn heatmap(
+    program: &[u16],
+    point: DumpPoint,
+    image_path: &str,
+    csv_path: Option<&str>,
+    input_path: Option<&str>,
+) {
+    let machine = run_to(
+        Machine::new(program.to_vec()).with_heatmap(),
+        point,
+        input_path,
+    );
+    let heatmap = machine.heatmap().expect("Heatmap should be enabled");
+
+    std::fs::write(image_path, heatmap.to_ppm())
+        .unwrap_or_else(|e| panic!("Could not write {image_path}: {e}"));
+    info!("Saved heatmap to {image_path}");
+
+    if let Some(path) = csv_path {
+        std::fs::write(path, heatmap.to_csv())
+            .unwrap_or_else(|e| panic!("Could not write {path}: {e}"));
+        info!("Saved access counts to {path}");
+    }
+}
+
+/// Print the differences between two memory images.
+fn diff_images(before_path: &str, after_path: &str, args: &Args) {
+    let load = |file_path: &str| {
+        let image = loader::load(Path::new(file_path), args.format.map(Into::into))
+            .unwrap_or_else(|e| panic!("Could not load {file_path}: {e:#}"));
+        Machine::new(image).into_state()
+    };
+
+    print!(
+        "{}",
+        Diff::new(&load(before_path), &load(after_path)).display(
+            &Project::open(Path::new(&args.project))
+                .unwrap_or_else(|e| panic!("Could not load project: {e:#}"))
+        )
+    );
+}
+
+/// Load the program and apply any patches to it.
+fn load_program(args: &Args) -> Vec<u16> {
+    let file_path = &args.program;
+
+    let mut program = loader::load(Path::new(file_path), args.format.map(Into::into))
+        .unwrap_or_else(|e| panic!("Could not load program {file_path}: {e:#}"));
+
+    for patch_path in &args.patch {
+        Patch::load(Path::new(patch_path))
+            .and_then(|patch| patch.apply(&mut program))
+            .unwrap_or_else(|e| panic!("Could not apply patch {patch_path}: {e:#}"));
+    }
+
+    program
+}
+
+fn manage_replays(replay_manager: &ReplayManager, command: &ReplaysCommand, args: &Args) {
+    match command {
+        ReplaysCommand::List => {
+            let date_format = format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+
+            for replay in replay_manager.info().expect("Error reading replay files") {
+                let modified = OffsetDateTime::from(replay.modified)
+                    .format(date_format)
+                    .expect("Error formatting replay date");
+
+                println!(
+                    "{:<20} {:>6} commands  {modified} UTC",
+                    replay.name, replay.commands
+                );
+            }
+        }
+
+        ReplaysCommand::Branches { name } => {
+            let replay = replay_manager
+                .load(name)
+                .unwrap_or_else(|e| panic!("Error reading replay file {name}: {e}"));
+
+            println!(
+                "{:<20} {:>6} commands",
+                MAIN_BRANCH,
+                replay.commands().count()
+            );
+
+            for branch in &replay.branches {
+                let commands = replay
+                    .path(&branch.name)
+                    .expect("Error reading branch")
+                    .commands()
+                    .count();
+
+                println!(
+                    "{:<20} {commands:>6} commands  forks from {} after {}",
+                    branch.name, branch.parent, branch.fork
+                );
+            }
+        }
+
+        ReplaysCommand::Delete { name } => {
+            replay_manager
+                .delete(name)
+                .unwrap_or_else(|e| panic!("Error deleting replay {name}: {e}"));
+        }
+
+        ReplaysCommand::Rename { from, to } => {
+            replay_manager
+                .rename(from, to)
+                .unwrap_or_else(|e| panic!("Error renaming replay {from} to {to}: {e}"));
+        }
+
+        ReplaysCommand::Test { bless } => {
+            let program = load_program(args);
+            let mut failures = 0;
+
+            for name in replay_manager
+                .replay_files()
+                .expect("Error reading replay files")
+            {
+                let replay = match replay_manager.load(&name) {
+                    Ok(replay) => replay,
+                    Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                        warn!("Skipping {name}, not a replay: {e}");
+                        continue;
+                    }
+                    Err(e) => panic!("Error reading replay file {name}: {e}"),
+                };
+
+                let actual = transcript::record(program.clone(), &replay);
+
+                if *bless {
+                    replay_manager
+                        .save_transcript(&name, &actual)
+                        .unwrap_or_else(|e| panic!("Error saving transcript for {name}: {e}"));
+                    println!("{name}: saved");
+                    continue;
+                }
+
+                match replay_manager.load_transcript(&name) {
+                    Ok(expected) => match transcript::diff(&expected, &actual) {
+                        None => println!("{name}: ok"),
+                        Some(diff) => {
+                            println!("{name}: MISMATCH\n{diff}");
+                            failures += 1;
+                        }
+                    },
+
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        println!("{name}: no transcript, run with --bless to create one");
+                        failures += 1;
+                    }
+
+                    Err(e) => panic!("Error reading transcript for {name}: {e}"),
+                }
+            }
+
+            if failures > 0 {
+                println!("{failures} replay(s) failed");
+                std::process::exit(1);
+            }
+        }
+
+        ReplaysCommand::Minimize {
+            name,
+            contains,
+            memory,
+            pc,
+            save_as,
+        } => {
+            let goal = match (contains, memory, pc) {
+                (Some(text), _, _) => Goal::Output(text.clone()),
+                (_, Some(goal), _) | (_, _, Some(goal)) => goal.clone(),
+                _ => unreachable!("clap requires a goal"),
+            };
+
+            let replay = replay_manager
+                .load(name)
+                .unwrap_or_else(|e| panic!("Error reading replay file {name}: {e}"));
+            let commands = replay.commands().cloned().collect::<Vec<String>>();
+
+            let program = load_program(args);
+            let program_hash = replay::program_hash(&program);
+
+            let mut minimizer = Minimizer::new(program, goal);
+            // Not reaching the goal is the user's mistake, not a bug
+            let minimized = match minimizer.minimize(&commands) {
+                Ok(minimized) => minimized,
+                Err(e) => {
+                    error!("Could not minimize replay {name}: {e:#}");
+                    std::process::exit(1);
+                }
+            };
+
+            println!(
+                "{name}: {} commands minimized to {} in {} runs",
+                commands.len(),
+                minimized.len(),
+                minimizer.runs()
+            );
+
+            let file_path = match save_as {
+                Some(save_as) => replay_manager.replay_path(save_as),
+                None => replay_manager.next_file_path(),
+            }
+            .expect("Error getting replay file path");
+
+            let mut minimized_replay = ReplayManager::new(Path::new(&args.replay_dir));
+            minimized_replay.set_header(ReplayHeader {
+                program_hash: Some(program_hash),
+                created: OffsetDateTime::now_utc().format(&Rfc3339).ok(),
+                author: replay.header.author,
+            });
+
+            let comment = Entry::Comment(format!("minimized from {name}"));
+            minimized_replay
+                .push_entry(comment)
+                .expect("Error storing replay entry");
+            for command in minimized {
+                minimized_replay
+                    .push(&command)
+                    .expect("Error storing replay entry");
+            }
+
+            minimized_replay
+                .save(&file_path, ReplayFormat::Structured)
+                .unwrap_or_else(|e| panic!("Error saving replay: {e}"));
+
+            println!("Saved {}", file_path.display());
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::{anyhow, bail};
+
+use crate::parse::parse_word;
+use crate::scan::Filter;
+
+/// Instructions shown by `!dis` when no count is given.
+pub const DEFAULT_DISASSEMBLE_COUNT: usize = 10;
+
+/// Input lines starting with this are handled by the player instead of being
+/// sent to the program, and are never recorded into the replay.
+pub const META_PREFIX: char = '!';
+
+#[derive(Debug, PartialEq)]
+pub enum MetaCommand {
+    /// Restore the state from before the last command
+    Undo,
+
+    /// Remember the current state under a name
+    Save(String),
+
+    /// Restore a state remembered with `Save`
+    Load(String),
+
+    /// Play the rest of the replay without waiting for input
+    Replay,
+
+    /// Stop playing and save the replay
+    Quit,
+
+    /// Show the pc and registers
+    Regs,
+
+    /// Set a register
+    Set { register: u16, value: u16 },
+
+    /// Write a word of memory
+    Poke { address: u16, value: u16 },
+
+    /// Show `len` words of memory
+    Peek { address: u16, len: u16 },
+
+    /// Show the stack
+    Stack,
+
+    /// Disassemble `count` instructions
+    Dis { address: u16, count: usize },
+
+    /// Start or narrow a memory scan
+    Scan(ScanCommand),
+
+    /// Name an address in the project file
+    Def { name: String, address: u16 },
+
+    /// Remove a name from the project file
+    Undef(String),
+
+    /// Show every named address with its value
+    Vars,
+
+    /// Write a value to a named address
+    Put { name: String, value: u16 },
+
+    /// Show what changed since a state remembered with `Save`, or since
+    /// before the last command
+    Diff(Option<String>),
+}
+
+/// What `!scan` does, see [`crate::scan::Scanner`].
+#[derive(Debug, PartialEq)]
+pub enum ScanCommand {
+    /// Start a scan of addresses holding a value, or of every address
+    Start(Option<u16>),
+
+    /// Narrow the scan by comparing memory against the last scan, or against
+    /// a state remembered with `Save`
+    Narrow {
+        filter: Filter,
+        since: Option<String>,
+    },
+
+    /// Show the candidates
+    List,
+}
+
+impl MetaCommand {
+    /// Parse a line of input, returning `None` if it isn't a meta command.
+    pub fn parse(line: &str) -> Option<anyhow::Result<Self>> {
+        let line = line.trim().strip_prefix(META_PREFIX)?;
+
+        Some(Self::parse_args(line.split_whitespace().collect()))
+    }
+
+    fn parse_args(args: Vec<&str>) -> anyhow::Result<Self> {
+        let name = |args: &[&str]| match args {
+            [name] => Ok(name.to_string()),
+            _ => Err(anyhow!("expected a name")),
+        };
+
+        Ok(match args.as_slice() {
+            ["undo"] => Self::Undo,
+            ["save", args @ ..] => Self::Save(name(args)?),
+            ["load", args @ ..] => Self::Load(name(args)?),
+            ["replay"] => Self::Replay,
+            ["quit"] => Self::Quit,
+            ["regs"] => Self::Regs,
+            ["set", register, value] => Self::Set {
+                register: parse_register(register)?,
+                value: parse_word(value)?,
+            },
+            ["poke", address, value] => Self::Poke {
+                address: parse_word(address)?,
+                value: parse_word(value)?,
+            },
+            ["peek", address] => Self::Peek {
+                address: parse_word(address)?,
+                len: 1,
+            },
+            ["peek", address, len] => Self::Peek {
+                address: parse_word(address)?,
+                len: parse_word(len)?,
+            },
+            ["stack"] => Self::Stack,
+            ["dis", address] => Self::Dis {
+                address: parse_word(address)?,
+                count: DEFAULT_DISASSEMBLE_COUNT,
+            },
+            ["dis", address, count] => Self::Dis {
+                address: parse_word(address)?,
+                count: parse_word(count)? as usize,
+            },
+            ["scan", args @ ..] => Self::Scan(parse_scan(args)?),
+            ["def", name, address] => Self::Def {
+                name: name.to_string(),
+                address: parse_word(address)?,
+            },
+            ["undef", args @ ..] => Self::Undef(name(args)?),
+            ["vars"] => Self::Vars,
+            ["diff"] => Self::Diff(None),
+            ["diff", name] => Self::Diff(Some(name.to_string())),
+            ["put", name, value] => Self::Put {
+                name: name.to_string(),
+                value: parse_word(value)?,
+            },
+            _ => bail!(
+                "unknown command, expected one of: {0}undo, {0}save <name>, {0}load <name>, \
+                 {0}replay, {0}quit, {0}regs, {0}set r<n> <value>, {0}poke <addr> <value>, \
+                 {0}peek <addr> [len], {0}stack, {0}dis <addr> [count], {0}scan <value|all>, \
+                 {0}scan <changed|unchanged|increased|decreased|eq <value>> [since <name>], \
+                 {0}scan list, {0}def <name> <addr>, {0}undef <name>, {0}vars, \
+                 {0}put <name> <value>, {0}diff [name]",
+                META_PREFIX
+            ),
+        })
+    }
+}
+
+/// Parse the arguments of `!scan`.
+fn parse_scan(args: &[&str]) -> anyhow::Result<ScanCommand> {
+    let (args, since) = match args {
+        [args @ .., "since", name] => (args, Some(name.to_string())),
+        _ => (args, None),
+    };
+
+    let filter = match args {
+        ["changed"] => Filter::Changed,
+        ["unchanged"] => Filter::Unchanged,
+        ["increased"] => Filter::Increased,
+        ["decreased"] => Filter::Decreased,
+        ["eq", value] => Filter::Equal(parse_word(value)?),
+        _ if since.is_some() => bail!("expected a filter before since"),
+        ["all"] => return Ok(ScanCommand::Start(None)),
+        ["list"] => return Ok(ScanCommand::List),
+        [value] => return Ok(ScanCommand::Start(Some(parse_word(value)?))),
+        _ => bail!("expected a value, all, list, or a filter"),
+    };
+
+    Ok(ScanCommand::Narrow { filter, since })
+}
+
+/// Parse a register name like `r7`.
+fn parse_register(input: &str) -> anyhow::Result<u16> {
+    input
+        .strip_prefix('r')
+        .ok_or_else(|| anyhow!("expected a register like r7, got {input}"))
+        .and_then(parse_word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert!(MetaCommand::parse("take tablet\n").is_none());
+        assert_eq!(
+            MetaCommand::parse("!undo\n").unwrap().unwrap(),
+            MetaCommand::Undo
+        );
+        assert_eq!(
+            MetaCommand::parse("  !save  bridge\n").unwrap().unwrap(),
+            MetaCommand::Save("bridge".to_string())
+        );
+        assert_eq!(
+            MetaCommand::parse("!set r7 0x10\n").unwrap().unwrap(),
+            MetaCommand::Set {
+                register: 7,
+                value: 16
+            }
+        );
+        assert_eq!(
+            MetaCommand::parse("!peek 5489\n").unwrap().unwrap(),
+            MetaCommand::Peek {
+                address: 5489,
+                len: 1
+            }
+        );
+        assert_eq!(
+            MetaCommand::parse("!scan 0x10\n").unwrap().unwrap(),
+            MetaCommand::Scan(ScanCommand::Start(Some(16)))
+        );
+        assert_eq!(
+            MetaCommand::parse("!scan eq 3 since hall\n")
+                .unwrap()
+                .unwrap(),
+            MetaCommand::Scan(ScanCommand::Narrow {
+                filter: Filter::Equal(3),
+                since: Some("hall".to_string())
+            })
+        );
+        assert!(MetaCommand::parse("!scan all since hall\n")
+            .unwrap()
+            .is_err());
+        assert_eq!(
+            MetaCommand::parse("!put room 0x10\n").unwrap().unwrap(),
+            MetaCommand::Put {
+                name: "room".to_string(),
+                value: 16
+            }
+        );
+        assert!(MetaCommand::parse("!load\n").unwrap().is_err());
+        assert!(MetaCommand::parse("!set 7 1\n").unwrap().is_err());
+        assert!(MetaCommand::parse("!fly\n").unwrap().is_err());
+    }
+}
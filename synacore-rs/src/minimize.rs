@@ -0,0 +1,239 @@
+use anyhow::{anyhow, bail, Context};
+
+use crate::events::Event;
+use crate::machine::Machine;
+use crate::parse::parse_word;
+
+/// Instructions a single command may run for before it is treated as stuck.
+const MAX_STEPS_PER_COMMAND: usize = 50_000_000;
+
+/// Something a replay is trying to achieve.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Goal {
+    /// The output of a command contains this text
+    Output(String),
+
+    /// The word at this address holds this value
+    Memory { address: usize, value: u16 },
+
+    /// Execution reaches this address
+    Pc(usize),
+}
+
+impl Goal {
+    /// Parse an `address=value` memory goal, each decimal or `0x` hex.
+    pub fn parse_memory(input: &str) -> anyhow::Result<Self> {
+        let (address, value) = input
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected address=value"))?;
+
+        Ok(Self::Memory {
+            address: parse_word(address.trim()).context("invalid address")? as usize,
+            value: parse_word(value.trim()).context("invalid value")?,
+        })
+    }
+
+    /// Parse a pc goal, decimal or `0x` hex.
+    pub fn parse_pc(input: &str) -> anyhow::Result<Self> {
+        Ok(Self::Pc(parse_word(input.trim())? as usize))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    Running,
+    Reached,
+    Dead,
+}
+
+/// Shrinks a list of commands to a smaller list that still reaches a goal.
+///
+/// Each candidate is run on a fork of the machine state left by the longest
+/// prefix it shares with the previous candidate, so only the commands after
+/// the point where they differ are executed.
+pub struct Minimizer {
+    goal: Goal,
+    initial: (Machine, Outcome),
+    cache: Vec<(String, Machine, Outcome)>,
+    runs: usize,
+}
+
+impl Minimizer {
+    pub fn new(program: Vec<u16>, goal: Goal) -> Self {
+        let mut machine = Machine::new(program);
+        let outcome = Self::run_until_input(&goal, &mut machine);
+
+        Self {
+            goal,
+            initial: (machine, outcome),
+            cache: Vec::new(),
+            runs: 0,
+        }
+    }
+
+    /// Number of candidate command lists tried so far.
+    pub fn runs(&self) -> usize {
+        self.runs
+    }
+
+    /// Number of commands run before the goal was reached, if it was.
+    pub fn reaches_goal(&mut self, commands: &[String]) -> Option<usize> {
+        self.runs += 1;
+
+        let shared = self
+            .cache
+            .iter()
+            .zip(commands)
+            .take_while(|((cached, _, _), command)| cached == *command)
+            .count();
+        self.cache.truncate(shared);
+
+        let (mut machine, outcome) = match self.cache.last() {
+            Some((_, machine, outcome)) => (machine.clone(), *outcome),
+            None => self.initial.clone(),
+        };
+
+        match outcome {
+            Outcome::Reached => return Some(shared),
+            Outcome::Dead => return None,
+            Outcome::Running => {}
+        }
+
+        for (i, command) in commands.iter().enumerate().skip(shared) {
+            machine.push_input(&format!("{command}\n"));
+            let outcome = Self::run_until_input(&self.goal, &mut machine);
+
+            self.cache.push((command.clone(), machine.clone(), outcome));
+
+            match outcome {
+                Outcome::Reached => return Some(i + 1),
+                Outcome::Dead => return None,
+                Outcome::Running => {}
+            }
+        }
+
+        None
+    }
+
+    /// Delta debugging: repeatedly try subsets and complements of ever
+    /// smaller chunks of the commands, keeping any that still reach the goal.
+    /// The result is 1-minimal, removing any single command loses the goal.
+    pub fn minimize(&mut self, commands: &[String]) -> anyhow::Result<Vec<String>> {
+        let Some(used) = self.reaches_goal(commands) else {
+            bail!("The replay does not reach the goal");
+        };
+
+        let mut commands = commands[..used].to_vec();
+        let mut chunks = 2;
+
+        while commands.len() >= 2 {
+            let chunk_len = commands.len().div_ceil(chunks);
+            let pieces = commands.chunks(chunk_len).collect::<Vec<&[String]>>();
+            let chunks_used = pieces.len();
+
+            let subsets = pieces.iter().map(|piece| (piece.to_vec(), 2));
+            let complements = (0..chunks_used).map(|i| {
+                let complement = pieces
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .flat_map(|(_, piece)| piece.iter().cloned())
+                    .collect::<Vec<String>>();
+
+                (complement, chunks_used - 1)
+            });
+
+            let reduced = subsets
+                .chain(complements)
+                .find_map(|(candidate, next_chunks)| {
+                    let used = self.reaches_goal(&candidate)?;
+                    Some((candidate[..used].to_vec(), next_chunks.max(2)))
+                });
+
+            match reduced {
+                Some((candidate, next_chunks)) => {
+                    commands = candidate;
+                    chunks = next_chunks;
+                }
+
+                None if chunks_used >= commands.len() => break,
+
+                None => chunks = (chunks_used * 2).min(commands.len()),
+            }
+        }
+
+        Ok(commands)
+    }
+
+    fn run_until_input(goal: &Goal, machine: &mut Machine) -> Outcome {
+        let mut output = String::new();
+        let mut events = machine.events().with_steps();
+
+        for _ in 0..MAX_STEPS_PER_COMMAND {
+            match events.next() {
+                Some(Event::Step { .. }) => {}
+                Some(Event::Output(s)) => output += &s,
+                Some(Event::NeedInput) => return Outcome::Running,
+                Some(Event::Halted | Event::Fault(_)) | None => return Outcome::Dead,
+            }
+
+            let machine = events.machine();
+            let reached = match goal {
+                Goal::Output(text) => output.contains(text.as_str()),
+                Goal::Memory { address, value } => machine.memory().get(*address) == Some(value),
+                Goal::Pc(pc) => machine.pc() == *pc,
+            };
+
+            if reached {
+                return Outcome::Reached;
+            }
+        }
+
+        Outcome::Dead
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::REGISTER_OFFSET;
+    use crate::parse::{ADD, EQ, IN, JF, JMP, OUT};
+
+    #[test]
+    fn test_minimize() {
+        // Count the "a" commands in r1, print "!" once three have been seen
+        #[rustfmt::skip]
+        let program = vec![
+            // 0: read a character into r0
+            IN, REGISTER_OFFSET,
+            // 2: r2 = r0 == 'a'
+            EQ, REGISTER_OFFSET + 2, REGISTER_OFFSET, 'a' as u16,
+            // 6: skip the count unless r2
+            JF, REGISTER_OFFSET + 2, 13,
+            // 9: r1 += 1
+            ADD, REGISTER_OFFSET + 1, REGISTER_OFFSET + 1, 1,
+            // 13: r2 = r1 == 3
+            EQ, REGISTER_OFFSET + 2, REGISTER_OFFSET + 1, 3,
+            // 17: loop unless r2
+            JF, REGISTER_OFFSET + 2, 0,
+            // 20: print "!" and wait for input forever
+            OUT, '!' as u16,
+            JMP, 0,
+        ];
+
+        let commands = ["b", "a", "c", "a", "b", "b", "a", "c", "a"]
+            .map(String::from)
+            .to_vec();
+
+        let mut minimizer = Minimizer::new(program.clone(), Goal::Output("!".to_string()));
+        assert_eq!(minimizer.minimize(&commands).unwrap(), ["a", "a", "a"]);
+
+        assert_eq!(Goal::parse_pc("0x14").unwrap(), Goal::Pc(20));
+        let mut minimizer = Minimizer::new(program.clone(), Goal::Pc(20));
+        assert_eq!(minimizer.minimize(&commands).unwrap(), ["a", "a", "a"]);
+
+        let mut minimizer = Minimizer::new(program, Goal::parse_memory("0x8001=2").unwrap());
+        assert_eq!(minimizer.minimize(&commands).unwrap(), ["a", "a"]);
+        assert!(minimizer.minimize(&commands[..3]).is_err());
+    }
+}
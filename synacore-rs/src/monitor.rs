@@ -0,0 +1,202 @@
+use std::ops::Range;
+
+use log::debug;
+
+use crate::machine::{Machine, U15_MAX};
+use crate::parse::Token;
+
+/// How a program modified its own code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModificationKind {
+    /// A word that had already been executed was written
+    Overwrote,
+
+    /// A word that had been written was executed
+    Executed,
+}
+
+/// The first time an address was modified in one of the ways of
+/// [`ModificationKind`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Modification {
+    pub kind: ModificationKind,
+    pub address: usize,
+
+    /// pc of the instruction that last wrote the address
+    pub writer: usize,
+}
+
+/// Watches a machine for self-modifying code, by tracking which words of
+/// main memory have been executed and which have been written.
+///
+/// Enable it with [`Machine::with_monitor`], which slows the `Blocks`
+/// engine down as described on [`Machine::run`].
+///
+/// It doesn't keep a copy of memory: a decryption loop may run some of the
+/// code it has written before it finishes writing the rest, so no point
+/// during the run is known to be after the last write. Take the machine's
+/// state once it stops instead, such as at its first input request.
+#[derive(Clone, Debug)]
+pub struct Monitor {
+    /// Whether each word has been part of an executed instruction
+    executed: Vec<bool>,
+
+    /// pc of the last instruction to write each word
+    writers: Vec<Option<usize>>,
+
+    /// Whether each word has been reported as overwritten and as executed
+    reported: Vec<[bool; 2]>,
+
+    modifications: Vec<Modification>,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        let len = U15_MAX as usize;
+
+        Self {
+            executed: vec![false; len],
+            writers: vec![None; len],
+            reported: vec![[false; 2]; len],
+            modifications: Vec::new(),
+        }
+    }
+}
+
+impl Monitor {
+    /// Every address found to be modified, in the order they were found.
+    pub fn modifications(&self) -> &[Modification] {
+        &self.modifications
+    }
+
+    /// Ranges of addresses that were both written and executed.
+    pub fn regions(&self) -> Vec<Range<usize>> {
+        let mut regions: Vec<Range<usize>> = Vec::new();
+
+        for address in 0..self.executed.len() {
+            if !self.executed[address] || self.writers[address].is_none() {
+                continue;
+            }
+
+            match regions.last_mut() {
+                Some(region) if region.end == address => region.end += 1,
+                _ => regions.push(address..address + 1),
+            }
+        }
+
+        regions
+    }
+
+    /// pc of the instruction that last wrote `address`.
+    pub fn writer(&self, address: usize) -> Option<usize> {
+        self.writers.get(address).copied().flatten()
+    }
+
+    fn report(&mut self, kind: ModificationKind, address: usize) {
+        let Some(writer) = self.writers[address] else {
+            return;
+        };
+
+        let reported = &mut self.reported[address][kind as usize];
+        if *reported {
+            return;
+        }
+        *reported = true;
+
+        debug!("Self-modifying code: {kind:?} {address} written by {writer}");
+        self.modifications.push(Modification {
+            kind,
+            address,
+            writer,
+        });
+    }
+}
+
+impl Machine {
+    /// Watch for self-modifying code from here on, see [`Monitor`].
+    pub fn with_monitor(mut self) -> Self {
+        self.monitor = Some(Box::default());
+        self
+    }
+
+    pub fn monitor(&self) -> Option<&Monitor> {
+        self.monitor.as_deref()
+    }
+
+    /// Record that the instruction at pc is about to be executed.
+    pub(crate) fn monitor_execute(&mut self, token: Token) {
+        let Some(monitor) = &mut self.monitor else {
+            return;
+        };
+
+        let words = self.pc..(self.pc + token.pc_delta()).min(U15_MAX as usize);
+
+        for address in words {
+            monitor.executed[address] = true;
+            monitor.report(ModificationKind::Executed, address);
+        }
+    }
+
+    /// Record that the instruction at pc is writing `address`.
+    pub(crate) fn monitor_write(&mut self, address: usize) {
+        let Some(monitor) = &mut self.monitor else {
+            return;
+        };
+
+        if address >= U15_MAX as usize {
+            return;
+        }
+
+        monitor.writers[address] = Some(self.pc);
+
+        if monitor.executed[address] {
+            monitor.report(ModificationKind::Overwrote, address);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::RunState;
+    use crate::parse::{HALT, JMP, NOOP, OUT, WMEM};
+
+    #[test]
+    fn test_monitor() {
+        #[rustfmt::skip]
+        let program = vec![
+            // 0: decrypt the out at 10, then run it
+            WMEM, 10, OUT,
+            JMP, 10,
+            NOOP, NOOP, NOOP, NOOP, NOOP,
+            // 10: encrypted "out 'a'"
+            OUT - 1, 'a' as u16,
+            // 12: overwrite the out after it has run
+            WMEM, 10, NOOP,
+            HALT,
+        ];
+
+        let mut machine = Machine::new(program).with_monitor();
+        assert_eq!(*machine.run(), RunState::BufferedOutput("a".to_string()));
+        assert_eq!(*machine.run(), RunState::Halt);
+
+        let monitor = machine.monitor().unwrap();
+
+        assert_eq!(monitor.regions(), vec![10..11]);
+        assert_eq!(
+            monitor.modifications(),
+            [
+                Modification {
+                    kind: ModificationKind::Executed,
+                    address: 10,
+                    writer: 0,
+                },
+                Modification {
+                    kind: ModificationKind::Overwrote,
+                    address: 10,
+                    writer: 12,
+                },
+            ]
+        );
+    }
+}
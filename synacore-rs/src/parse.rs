@@ -1,3 +1,5 @@
+use anyhow::Context;
+
 pub const HALT: u16 = 0;
 pub const SET: u16 = 1;
 pub const PUSH: u16 = 2;
@@ -21,7 +23,9 @@ pub const OUT: u16 = 19;
 pub const IN: u16 = 20;
 pub const NOOP: u16 = 21;
 
-#[derive(Debug)]
+/// A decoded instruction with its raw arguments. Arguments of
+/// `REGISTER_OFFSET` and above name registers.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Token {
     // halt: 0
     //   stop execution and terminate the program
@@ -117,7 +121,7 @@ pub enum Token {
 impl Token {
     /// Parse the next token out of a slice of u16's
     pub fn parse(input: &[u16]) -> Option<Self> {
-        let val = input.get(0)?;
+        let val = input.first()?;
         Some(match *val {
             HALT => Self::Halt,
 
@@ -264,6 +268,34 @@ impl Token {
         })
     }
 
+    /// The instruction's arguments, in order.
+    pub fn args(&self) -> Vec<u16> {
+        match *self {
+            Self::Halt | Self::Ret() | Self::Noop | Self::Unknown(_) => vec![],
+            Self::Push(a)
+            | Self::Pop(a)
+            | Self::Jmp(a)
+            | Self::Call(a)
+            | Self::Out(a)
+            | Self::In(a) => {
+                vec![a]
+            }
+            Self::Set(a, b)
+            | Self::Jt(a, b)
+            | Self::Jf(a, b)
+            | Self::Not(a, b)
+            | Self::Rmem(a, b)
+            | Self::Wmem(a, b) => vec![a, b],
+            Self::Eq(a, b, c)
+            | Self::Gt(a, b, c)
+            | Self::Add(a, b, c)
+            | Self::Mult(a, b, c)
+            | Self::Mod(a, b, c)
+            | Self::And(a, b, c)
+            | Self::Or(a, b, c) => vec![a, b, c],
+        }
+    }
+
     /// Number to increment the program counter by to move past this instruction.
     pub fn pc_delta(&self) -> usize {
         match *self {
@@ -319,3 +351,12 @@ pub fn decompile(program: &[u16]) -> String {
 
     output
 }
+
+/// Parse a decimal or `0x` prefixed hex number.
+pub fn parse_word(input: &str) -> anyhow::Result<u16> {
+    match input.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => input.parse::<u16>(),
+    }
+    .with_context(|| format!("invalid number: {input}"))
+}
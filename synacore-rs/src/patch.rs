@@ -0,0 +1,231 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::machine::{NUM_REGISTERS, REGISTER_OFFSET};
+use crate::parse::parse_word;
+
+/// A set of memory and register edits applied to a program before it is run.
+///
+/// Patch files are plain text, one edit per line. `#` starts a comment.
+///
+/// ```text
+/// # write two noops at 5489
+/// 5489: 21 21
+///
+/// # same, but refuse to apply unless 5489 currently holds `call 1531`
+/// 5489: 17 1531 -> 21 21
+///
+/// # set register 7 before the program starts
+/// r7 = 25734
+/// ```
+///
+/// Numbers may be decimal or `0x` prefixed hex.
+#[derive(Debug, Default, PartialEq)]
+pub struct Patch {
+    edits: Vec<Edit>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Edit {
+    Memory {
+        address: usize,
+        expected: Option<Vec<u16>>,
+        words: Vec<u16>,
+    },
+    Register {
+        register: u16,
+        value: u16,
+    },
+}
+
+impl Patch {
+    pub fn load(file_path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(file_path)
+            .with_context(|| format!("Could not read patch file {}", file_path.display()))?;
+
+        Self::parse(&contents)
+            .with_context(|| format!("Could not parse patch file {}", file_path.display()))
+    }
+
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut edits = Vec::new();
+
+        for (line_num, line) in input.lines().enumerate() {
+            let line = match line.split_once('#') {
+                Some((line, _comment)) => line,
+                None => line,
+            }
+            .trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let edit =
+                Edit::parse(line).with_context(|| format!("line {}: {line}", line_num + 1))?;
+            edits.push(edit);
+        }
+
+        Ok(Self { edits })
+    }
+
+    /// Apply every edit to `program`. Registers are written to the register
+    /// slots that follow main memory, growing the program image as needed.
+    ///
+    /// All expected words are checked before anything is written, so a patch
+    /// that does not match leaves `program` untouched.
+    pub fn apply(&self, program: &mut Vec<u16>) -> anyhow::Result<()> {
+        for edit in &self.edits {
+            if let Edit::Memory {
+                address,
+                expected: Some(expected),
+                ..
+            } = edit
+            {
+                let found = (0..expected.len())
+                    .map(|i| program.get(address + i).copied().unwrap_or(0))
+                    .collect::<Vec<u16>>();
+
+                if found != *expected {
+                    bail!("Patch mismatch at {address}: expected {expected:?}, found {found:?}");
+                }
+            }
+        }
+
+        for edit in &self.edits {
+            let (address, words) = match edit {
+                Edit::Memory { address, words, .. } => (*address, words.as_slice()),
+                Edit::Register { register, value } => (
+                    (REGISTER_OFFSET + register) as usize,
+                    std::slice::from_ref(value),
+                ),
+            };
+
+            if program.len() < address + words.len() {
+                program.resize(address + words.len(), 0);
+            }
+
+            program[address..address + words.len()].copy_from_slice(words);
+        }
+
+        Ok(())
+    }
+}
+
+impl Edit {
+    fn parse(line: &str) -> anyhow::Result<Self> {
+        if let Some((register, value)) = line.split_once('=') {
+            let register = register
+                .trim()
+                .strip_prefix('r')
+                .ok_or_else(|| anyhow!("expected a register like r7"))?;
+            let register = parse_word(register)?;
+
+            if register >= NUM_REGISTERS {
+                bail!("register out of bounds: {register}");
+            }
+
+            return Ok(Self::Register {
+                register,
+                value: parse_word(value.trim())?,
+            });
+        }
+
+        let (address, words) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("expected `address: words` or `rN = value`"))?;
+        let address = parse_word(address.trim())? as usize;
+
+        if address >= REGISTER_OFFSET as usize {
+            bail!("address out of bounds: {address}");
+        }
+
+        let (expected, words) = match words.split_once("->") {
+            Some((expected, words)) => (Some(parse_words(expected)?), parse_words(words)?),
+            None => (None, parse_words(words)?),
+        };
+
+        if words.is_empty() {
+            bail!("no words to write");
+        }
+
+        if address + words.len() > REGISTER_OFFSET as usize {
+            bail!("patch at {address} runs past the end of memory");
+        }
+
+        Ok(Self::Memory {
+            address,
+            expected,
+            words,
+        })
+    }
+}
+
+fn parse_words(input: &str) -> anyhow::Result<Vec<u16>> {
+    input.split_whitespace().map(parse_word).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let patch = Patch::parse(
+            "# comment\n\
+             5489: 17 1531 -> 21 21\n\
+             \n\
+             0x10: 1   # trailing comment\n\
+             r7 = 25734\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            patch.edits,
+            vec![
+                Edit::Memory {
+                    address: 5489,
+                    expected: Some(vec![17, 1531]),
+                    words: vec![21, 21],
+                },
+                Edit::Memory {
+                    address: 16,
+                    expected: None,
+                    words: vec![1],
+                },
+                Edit::Register {
+                    register: 7,
+                    value: 25734,
+                },
+            ]
+        );
+
+        assert!(Patch::parse("r8 = 1").is_err());
+        assert!(Patch::parse("32767: 1 2").is_err());
+        assert!(Patch::parse("12: ").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let patch = Patch::parse("1: 2 3 -> 9 9\nr0 = 5").unwrap();
+
+        let mut program = vec![1, 2, 3, 4];
+        patch.apply(&mut program).unwrap();
+
+        assert_eq!(program[0..4], [1, 9, 9, 4]);
+        assert_eq!(program.len(), REGISTER_OFFSET as usize + 1);
+        assert_eq!(program[REGISTER_OFFSET as usize], 5);
+    }
+
+    #[test]
+    fn test_apply_mismatch() {
+        let patch = Patch::parse("0: 7 -> 9\n1: 2 -> 9").unwrap();
+
+        let mut program = vec![1, 2, 3, 4];
+        assert!(patch.apply(&mut program).is_err());
+
+        assert_eq!(program, [1, 2, 3, 4]);
+    }
+}
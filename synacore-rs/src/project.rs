@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::machine::{NUM_REGISTERS, REGISTER_OFFSET};
+use crate::parse::parse_word;
+
+/// Project file used when none is given.
+pub const PROJECT_FILE: &str = "project.txt";
+
+/// Names given to addresses of game state, so they can be read and changed
+/// by name.
+///
+/// Project files are plain text, one name per line, and `#` starts a
+/// comment. Saving keeps the file as it was apart from the definitions that
+/// changed: those are rewritten in place, removed ones are dropped and new
+/// ones are added at the end.
+///
+/// ```text
+/// # the room the player is in
+/// room = 2732
+/// tablet_location = 0xa6e
+/// ```
+#[derive(Debug, Default, PartialEq)]
+pub struct Project {
+    symbols: BTreeMap<String, usize>,
+
+    /// Lines of the file the project was parsed from
+    lines: Vec<String>,
+
+    /// Line and address of each name as the file last defined it
+    defined: BTreeMap<String, (usize, usize)>,
+}
+
+impl Project {
+    pub fn load(file_path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(file_path)
+            .with_context(|| format!("Could not read project file {}", file_path.display()))?;
+
+        Self::parse(&contents)
+            .with_context(|| format!("Could not parse project file {}", file_path.display()))
+    }
+
+    /// Load the project file, or start an empty project if there isn't one.
+    pub fn open(file_path: &Path) -> anyhow::Result<Self> {
+        if file_path.try_exists()? {
+            Self::load(file_path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, file_path: &Path) -> anyhow::Result<()> {
+        fs::write(file_path, self.to_string())
+            .with_context(|| format!("Could not write project file {}", file_path.display()))
+    }
+
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut project = Self::default();
+
+        for (line_num, line) in input.lines().enumerate() {
+            let (definition, _comment) = split_comment(line);
+
+            if definition.is_empty() {
+                continue;
+            }
+
+            let (name, address) = definition
+                .split_once('=')
+                .ok_or_else(|| anyhow!("line {}: expected `name = address`", line_num + 1))?;
+            let (name, address) = (name.trim(), parse_word(address.trim())? as usize);
+
+            project
+                .define(name, address)
+                .with_context(|| format!("line {}: {definition}", line_num + 1))?;
+            project
+                .defined
+                .insert(name.to_string(), (line_num, address));
+        }
+
+        project.lines = input.lines().map(String::from).collect();
+
+        Ok(project)
+    }
+
+    /// Name an address of main memory or a register, replacing any address
+    /// the name had before.
+    pub fn define(&mut self, name: &str, address: usize) -> anyhow::Result<()> {
+        let is_valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if !is_valid_name {
+            bail!("invalid name {name:?}, expected letters, digits and underscores");
+        }
+
+        if address >= (REGISTER_OFFSET + NUM_REGISTERS) as usize {
+            bail!("address out of bounds: {address}");
+        }
+
+        self.symbols.insert(name.to_string(), address);
+
+        Ok(())
+    }
+
+    /// Forget a name, returning false if it wasn't defined.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.symbols.remove(name).is_some()
+    }
+
+    pub fn address(&self, name: &str) -> Option<usize> {
+        self.symbols.get(name).copied()
+    }
+
+    /// The first name, alphabetically, given to an address.
+    pub fn name(&self, address: usize) -> Option<&str> {
+        self.symbols()
+            .find(|(_, a)| *a == address)
+            .map(|(name, _)| name)
+    }
+
+    /// Every name with its address, by name.
+    pub fn symbols(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.symbols
+            .iter()
+            .map(|(name, address)| (name.as_str(), *address))
+    }
+}
+
+/// Split a line into what comes before any `#`, trimmed, and the comment.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    match line.split_once('#') {
+        Some((definition, comment)) => (definition.trim(), Some(comment)),
+        None => (line.trim(), None),
+    }
+}
+
+impl fmt::Display for Project {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (line_num, line) in self.lines.iter().enumerate() {
+            let (definition, comment) = split_comment(line);
+
+            let Some((name, _)) = definition.split_once('=') else {
+                writeln!(f, "{line}")?;
+                continue;
+            };
+            let name = name.trim();
+
+            // Only the last definition of a name counts, and only if the
+            // name is still defined
+            let Some((defined_on, defined_as)) = self.defined.get(name) else {
+                continue;
+            };
+            let Some(address) = self.address(name) else {
+                continue;
+            };
+            if *defined_on != line_num {
+                continue;
+            }
+
+            if address == *defined_as {
+                writeln!(f, "{line}")?;
+            } else if let Some(comment) = comment {
+                writeln!(f, "{name} = {address}  #{comment}")?;
+            } else {
+                writeln!(f, "{name} = {address}")?;
+            }
+        }
+
+        for (name, address) in self.symbols() {
+            if !self.defined.contains_key(name) {
+                writeln!(f, "{name} = {address}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut project = Project::parse(
+            "# comment\n\
+             room = 2732\n\
+             \n\
+             tablet = 0xa6e   # trailing comment\n",
+        )
+        .unwrap();
+
+        assert_eq!(project.address("room"), Some(2732));
+        assert_eq!(project.name(0xa6e), Some("tablet"));
+        assert_eq!(Project::parse(&project.to_string()).unwrap(), project);
+
+        project.define("r7", REGISTER_OFFSET as usize + 7).unwrap();
+        assert!(project.remove("room"));
+        assert!(!project.remove("room"));
+        assert_eq!(
+            project.to_string(),
+            "# comment\n\ntablet = 0xa6e   # trailing comment\nr7 = 32775\n"
+        );
+
+        project.define("tablet", 2671).unwrap();
+        assert_eq!(
+            project.to_string(),
+            "# comment\n\ntablet = 2671  # trailing comment\nr7 = 32775\n"
+        );
+
+        let project = Project::parse("room = 1\nroom = 2\n").unwrap();
+        assert_eq!(project.address("room"), Some(2));
+        assert_eq!(project.to_string(), "room = 2\n");
+
+        assert!(Project::parse("room 2732\n").is_err());
+        assert!(Project::parse("the room = 2732\n").is_err());
+        assert!(Project::parse("room = 40000\n").is_err());
+    }
+}
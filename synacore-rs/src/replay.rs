@@ -1,61 +1,552 @@
-use std::fs::File;
-use std::io::prelude::*;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::SystemTime;
 
+use anyhow::{anyhow, bail, Context};
+use log::warn;
 use regex::Regex;
 
 pub const REPLAY_SAVE_DIR: &str = "replays";
 
+/// Subdirectory of the replay directory holding expected output transcripts.
+pub const TRANSCRIPT_DIR: &str = "transcripts";
+
+/// Name of the branch made up of the entries before any `@branch` line.
+pub const MAIN_BRANCH: &str = "main";
+
+/// First line of a replay in the structured format. Files without it are
+/// read as legacy replays, one command per line.
+const REPLAY_FORMAT_MAGIC: &str = "#! synacore-replay 1";
+
+/// Prefix of journal files in the replay directory. Journals are hidden from
+/// the replay list and turned into numbered replays when they are finished.
+/// The session writing a journal holds a lock on it until it ends.
+const JOURNAL_PREFIX: &str = ".journal_";
+
+/// Records the replay of the current session and manages the replays saved
+/// in a directory.
 pub struct ReplayManager {
-    commands: Vec<String>,
+    replay_dir: PathBuf,
+    replay: Replay,
+    journal: Option<(PathBuf, File)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplayFormat {
+    /// Header, comments, checkpoints and expect directives
+    Structured,
+
+    /// Bare list of input lines
+    Legacy,
+}
+
+/// A recorded list of commands, with optional metadata and directives.
+///
+/// ```text
+/// #! synacore-replay 1
+/// #! program: 5e2a7b6c9d0f1a3e
+/// #! created: 2026-01-01T12:00:00Z
+/// #! author: someone
+/// # a comment
+/// take tablet
+/// @expect Taken.
+/// @checkpoint foyer
+/// \@literal command starting with @ or #
+/// @branch south main 1
+/// go south
+/// ```
+///
+/// Replays form a tree of commands. `entries` is the main branch, and every
+/// `@branch <name> <parent> <fork>` line starts a branch that continues from
+/// the first `fork` commands of its parent branch.
+#[derive(Debug, Default, PartialEq)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub entries: Vec<Entry>,
+    pub branches: Vec<Branch>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Branch {
+    pub name: String,
+    pub parent: String,
+    /// Number of the parent's commands this branch follows before its own
+    pub fork: usize,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayHeader {
+    pub program_hash: Option<String>,
+    pub created: Option<String>,
+    pub author: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Entry {
+    /// A line of input, without its trailing newline
+    Command(String),
+
+    Comment(String),
+
+    /// A named point in the replay that playback can stop at
+    Checkpoint(String),
+
+    /// Text that must appear in the output of the preceding command
+    Expect(String),
+}
+
+impl Replay {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut lines = input.lines().peekable();
+
+        if lines.peek() != Some(&REPLAY_FORMAT_MAGIC) {
+            return Ok(Self {
+                entries: lines.map(|line| Entry::Command(line.to_string())).collect(),
+                ..Default::default()
+            });
+        }
+
+        lines.next();
+
+        let mut replay = Self::default();
+
+        for (line_num, line) in lines.enumerate() {
+            if let Some(branch) = line.strip_prefix("@branch ") {
+                let branch = Branch::parse(branch)
+                    .with_context(|| format!("line {}: {line}", line_num + 2))?;
+
+                replay.add_branch(branch)?;
+                continue;
+            }
+
+            let entries = match replay.branches.last_mut() {
+                Some(branch) => &mut branch.entries,
+                None => &mut replay.entries,
+            };
+
+            if let Some(header) = line.strip_prefix("#!") {
+                let (key, value) = header.split_once(':').unwrap_or((header, ""));
+                let value = Some(value.trim().to_string());
+
+                match key.trim() {
+                    "program" => replay.header.program_hash = value,
+                    "created" => replay.header.created = value,
+                    "author" => replay.header.author = value,
+                    _ => {}
+                }
+            } else if let Some(comment) = line.strip_prefix('#') {
+                entries.push(Entry::Comment(comment.trim().to_string()));
+            } else if let Some(name) = line.strip_prefix("@checkpoint ") {
+                entries.push(Entry::Checkpoint(name.trim().to_string()));
+            } else if let Some(text) = line.strip_prefix("@expect ") {
+                entries.push(Entry::Expect(text.to_string()));
+            } else {
+                let command = line.strip_prefix('\\').unwrap_or(line);
+                entries.push(Entry::Command(command.to_string()));
+            }
+        }
+
+        Ok(replay)
+    }
+
+    pub fn to_string(&self, format: ReplayFormat) -> String {
+        let mut output = String::new();
+
+        if format == ReplayFormat::Legacy {
+            for command in self.commands() {
+                output += command;
+                output.push('\n');
+            }
+
+            return output;
+        }
+
+        output += REPLAY_FORMAT_MAGIC;
+        output.push('\n');
+
+        let header = [
+            ("program", &self.header.program_hash),
+            ("created", &self.header.created),
+            ("author", &self.header.author),
+        ];
+
+        for (key, value) in header {
+            if let Some(value) = value {
+                let _ = writeln!(output, "#! {key}: {value}");
+            }
+        }
+
+        for entry in &self.entries {
+            output += &entry.to_line();
+        }
+
+        for branch in &self.branches {
+            let _ = writeln!(
+                output,
+                "@branch {} {} {}",
+                branch.name, branch.parent, branch.fork
+            );
+
+            for entry in &branch.entries {
+                output += &entry.to_line();
+            }
+        }
+
+        output
+    }
+
+    pub fn branch_names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(MAIN_BRANCH).chain(self.branches.iter().map(|branch| branch.name.as_str()))
+    }
+
+    /// Add a branch to the tree. Its parent must already exist and have at
+    /// least `fork` commands.
+    pub fn add_branch(&mut self, branch: Branch) -> anyhow::Result<()> {
+        if self.branch_names().any(|name| name == branch.name) {
+            bail!("Branch {} already exists", branch.name);
+        }
+
+        let parent_commands = self.path(&branch.parent)?.commands().count();
+        if branch.fork > parent_commands {
+            bail!(
+                "Branch {} forks after command {} but {} only has {parent_commands}",
+                branch.name,
+                branch.fork,
+                branch.parent
+            );
+        }
+
+        self.branches.push(branch);
+
+        Ok(())
+    }
+
+    /// The entries from the root of the tree to the end of a branch, as a
+    /// replay without branches.
+    pub fn path(&self, branch_name: &str) -> anyhow::Result<Replay> {
+        if branch_name == MAIN_BRANCH {
+            return Ok(Replay {
+                header: self.header.clone(),
+                entries: self.entries.clone(),
+                branches: vec![],
+            });
+        }
+
+        let branch = self
+            .branches
+            .iter()
+            .find(|branch| branch.name == branch_name)
+            .ok_or_else(|| anyhow!("No branch named {branch_name}"))?;
+
+        let mut path = self.path(&branch.parent)?;
+
+        // Keep everything before the parent's next command after the fork
+        path.entries
+            .truncate(command_index(&path.entries, branch.fork));
+        path.entries.extend(branch.entries.iter().cloned());
+
+        Ok(path)
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().filter_map(|entry| match entry {
+            Entry::Command(command) => Some(command),
+            _ => None,
+        })
+    }
+}
+
+impl Branch {
+    /// Parse the `<name> <parent> <fork>` arguments of a `@branch` line.
+    fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut args = input.split_whitespace();
+
+        let (Some(name), Some(parent), Some(fork), None) =
+            (args.next(), args.next(), args.next(), args.next())
+        else {
+            bail!("expected @branch <name> <parent> <fork>");
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            parent: parent.to_string(),
+            fork: fork.parse().context("invalid fork")?,
+            entries: vec![],
+        })
+    }
+}
+
+impl Entry {
+    /// The entry as a line of a structured replay, including the newline.
+    fn to_line(&self) -> String {
+        match self {
+            Self::Command(command) if command.starts_with(['#', '@', '\\']) => {
+                format!("\\{command}\n")
+            }
+            Self::Command(command) => format!("{command}\n"),
+            Self::Comment(comment) => format!("# {comment}\n"),
+            Self::Checkpoint(name) => format!("@checkpoint {name}\n"),
+            Self::Expect(text) => format!("@expect {text}\n"),
+        }
+    }
+}
+
+/// Index of the `n`th command in `entries`, counting from 0, or the length
+/// of `entries` if there are not that many commands.
+fn command_index(entries: &[Entry], n: usize) -> usize {
+    entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| matches!(entry, Entry::Command(_)))
+        .nth(n)
+        .map_or(entries.len(), |(i, _)| i)
+}
+
+fn read_replay(file_path: &Path) -> io::Result<Replay> {
+    Replay::parse(&fs::read_to_string(file_path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e:#}")))
+}
+
+/// Identify a program so replays can tell which program they were recorded
+/// against. This is 64 bit FNV-1a over the program's words.
+pub fn program_hash(program: &[u16]) -> String {
+    let hash = program
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+
+    format!("{hash:016x}")
+}
+
+/// Summary of a replay file on disk.
+pub struct ReplayInfo {
+    pub name: String,
+    pub commands: usize,
+    pub modified: SystemTime,
 }
 
 impl ReplayManager {
-    pub fn new() -> Self {
+    pub fn new(replay_dir: &Path) -> Self {
         Self {
-            commands: Vec::new(),
+            replay_dir: replay_dir.to_path_buf(),
+            replay: Replay::default(),
+            journal: None,
         }
     }
 
-    pub fn push(&mut self, command: String) -> Option<&String> {
-        self.commands.push(command);
+    pub fn replay_dir(&self) -> &Path {
+        &self.replay_dir
+    }
+
+    pub fn set_header(&mut self, header: ReplayHeader) {
+        self.replay.header = header;
+    }
+
+    /// Start writing everything recorded so far, and every entry pushed from
+    /// now on, to a journal file in the replay directory. If the session ends
+    /// without `save` the journal is recovered by `recover_journals`.
+    ///
+    /// The journal stays locked for as long as this manager has it open, so
+    /// that other sessions don't recover it while it's in use.
+    pub fn start_journal(&mut self) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.replay_dir)?;
+
+        let journal_path = self
+            .replay_dir
+            .join(format!("{JOURNAL_PREFIX}{}", std::process::id()));
+
+        let mut file = File::create(&journal_path)?;
+        file.lock()?;
+        file.write_all(self.replay.to_string(ReplayFormat::Structured).as_bytes())?;
+
+        self.journal = Some((journal_path.clone(), file));
 
-        self.commands.last()
+        Ok(journal_path)
     }
 
-    pub fn save(self, file_path: &Path) -> std::io::Result<()> {
-        let replay_dir_path = Path::new(REPLAY_SAVE_DIR);
+    /// Record a line of input.
+    pub fn push(&mut self, line: &str) -> io::Result<()> {
+        let command = line.strip_suffix('\n').unwrap_or(line);
 
-        if replay_dir_path.try_exists()? == false {
-            std::fs::create_dir_all(replay_dir_path)?;
+        self.push_entry(Entry::Command(command.to_string()))
+    }
+
+    pub fn push_entry(&mut self, entry: Entry) -> io::Result<()> {
+        if let Some((_, file)) = &mut self.journal {
+            file.write_all(entry.to_line().as_bytes())?;
         }
 
-        let mut file = File::create(file_path)?;
+        self.replay.entries.push(entry);
 
-        for command in self.commands {
-            file.write_all(command.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn save(self, file_path: &Path, format: ReplayFormat) -> io::Result<()> {
+        if !self.replay_dir.try_exists()? {
+            fs::create_dir_all(&self.replay_dir)?;
+        }
+
+        fs::write(file_path, self.replay.to_string(format))?;
+
+        self.discard()
+    }
+
+    /// Save the recording as a new branch of the replay called `replay_name`
+    /// instead of as a replay of its own. The branch forks from `parent` at
+    /// the first command where the recording differs from it.
+    pub fn save_as_branch(
+        self,
+        replay_name: &str,
+        branch_name: &str,
+        parent: &str,
+    ) -> io::Result<()> {
+        let mut replay = self.load(replay_name)?;
+
+        let parent_path = replay
+            .path(parent)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{e:#}")))?;
+
+        let fork = self
+            .replay
+            .commands()
+            .zip(parent_path.commands())
+            .take_while(|(recorded, parent)| recorded == parent)
+            .count();
+        let fork_index = command_index(&self.replay.entries, fork);
+
+        replay
+            .add_branch(Branch {
+                name: branch_name.to_string(),
+                parent: parent.to_string(),
+                fork,
+                entries: self.replay.entries[fork_index..].to_vec(),
+            })
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{e:#}")))?;
+
+        fs::write(
+            self.replay_path(replay_name)?,
+            replay.to_string(ReplayFormat::Structured),
+        )?;
+
+        self.discard()
+    }
+
+    /// Everything recorded so far.
+    pub fn entries(&self) -> &[Entry] {
+        &self.replay.entries
+    }
+
+    /// Replace everything recorded so far, for example to undo commands.
+    pub fn restore(&mut self, entries: Vec<Entry>) -> io::Result<()> {
+        self.replay.entries = entries;
+
+        if let Some((_, file)) = &mut self.journal {
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(self.replay.to_string(ReplayFormat::Structured).as_bytes())?;
         }
 
         Ok(())
     }
 
-    pub fn replay_files() -> std::io::Result<Vec<String>> {
-        let replay_dir_path = Path::new(REPLAY_SAVE_DIR);
+    /// Stop recording without saving, removing the journal if there is one.
+    pub fn discard(self) -> io::Result<()> {
+        if let Some((journal_path, file)) = self.journal {
+            drop(file);
+            fs::remove_file(journal_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Turn a journal into the next numbered replay, returning its path.
+    /// Journals without any commands are removed instead.
+    pub fn finalize_journal(
+        &self,
+        journal_path: &Path,
+        format: ReplayFormat,
+    ) -> io::Result<Option<PathBuf>> {
+        let replay = read_replay(journal_path)?;
+
+        if replay.commands().next().is_none() {
+            fs::remove_file(journal_path)?;
+            return Ok(None);
+        }
+
+        let file_path = self.next_file_path()?;
+        fs::write(&file_path, replay.to_string(format))?;
+        fs::remove_file(journal_path)?;
+
+        Ok(Some(file_path))
+    }
 
-        if replay_dir_path.try_exists()? == false {
+    /// Finalize journals left behind by sessions that crashed or were killed.
+    ///
+    /// Journals that are still locked belong to sessions that are running,
+    /// and are left alone.
+    pub fn recover_journals(&self, format: ReplayFormat) -> io::Result<Vec<PathBuf>> {
+        if !self.replay_dir.try_exists()? {
             return Ok(vec![]);
         }
 
-        let replay_file_regex = Regex::new(r"(replay_[\d]+)").unwrap();
+        let own_journal = self.journal.as_ref().map(|(journal_path, _)| journal_path);
 
-        let mut replay_files = replay_dir_path
+        let mut journals = self
+            .replay_dir
+            .read_dir()?
+            .filter_map(|e| {
+                let path = e.ok()?.path();
+                let name = path.file_name()?.to_str()?;
+
+                if name.starts_with(JOURNAL_PREFIX) && Some(&path) != own_journal {
+                    Some(path)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<PathBuf>>();
+
+        journals.sort();
+
+        let mut recovered = Vec::new();
+        for journal_path in journals {
+            // Keep the lock until the journal is removed, so that another
+            // session recovering at the same time skips it
+            let journal = File::open(&journal_path)?;
+            match journal.try_lock() {
+                Ok(()) => {}
+                Err(fs::TryLockError::WouldBlock) => continue,
+                Err(fs::TryLockError::Error(e)) => return Err(e),
+            }
+
+            recovered.extend(self.finalize_journal(&journal_path, format)?);
+        }
+
+        Ok(recovered)
+    }
+
+    /// Names of all replay files, numbered replays first in numeric order,
+    /// followed by any renamed replays in alphabetical order.
+    pub fn replay_files(&self) -> io::Result<Vec<String>> {
+        if !self.replay_dir.try_exists()? {
+            return Ok(vec![]);
+        }
+
+        let mut replay_files = self
+            .replay_dir
             .read_dir()?
             .filter_map(|e| {
                 if let Ok(e) = e {
                     if e.file_type().ok()?.is_file() {
-                        let path = e.file_name().into_string().ok()?;
-                        if replay_file_regex.is_match(&path) {
-                            return Some(path);
+                        let name = e.file_name().into_string().ok()?;
+                        if !name.starts_with('.') {
+                            return Some(name);
                         }
                     }
                 }
@@ -64,28 +555,341 @@ impl ReplayManager {
             })
             .collect::<Vec<String>>();
 
-        replay_files.sort();
+        replay_files.sort_by_cached_key(|name| {
+            let file_num = Self::replay_number(name);
+            (file_num.is_none(), file_num, name.clone())
+        });
 
         Ok(replay_files)
-        
     }
 
-    pub fn next_file_path() -> std::io::Result<PathBuf> {
-        let mut replay_files = Self::replay_files()?;
+    /// The most recently numbered replay, if there is one.
+    pub fn last_replay(&self) -> io::Result<Option<String>> {
+        Ok(self
+            .replay_files()?
+            .into_iter()
+            .rfind(|name| Self::replay_number(name).is_some()))
+    }
 
+    pub fn next_file_path(&self) -> io::Result<PathBuf> {
         // return 1 higher then the highest numbered replay file found
-        while let Some(replay_file) = replay_files.pop() {
-            if let Some((_, file_num)) = replay_file.split_once('_') {
-                if let Ok(file_num) = file_num.parse::<u32>() {
-                    return Ok(PathBuf::from(&format!(
-                        "{REPLAY_SAVE_DIR}/replay_{}",
-                        file_num + 1
-                    )));
+        let file_num = match self.last_replay()? {
+            Some(name) => Self::replay_number(&name).unwrap_or(0) + 1,
+            None => 1,
+        };
+
+        Ok(self.replay_dir.join(format!("replay_{file_num}")))
+    }
+
+    /// Path of the replay called `name`, which must be a bare file name.
+    pub fn replay_path(&self, name: &str) -> io::Result<PathBuf> {
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid replay name: {name}"),
+            ));
+        }
+
+        Ok(self.replay_dir.join(name))
+    }
+
+    pub fn load(&self, name: &str) -> io::Result<Replay> {
+        read_replay(&self.replay_path(name)?)
+    }
+
+    /// Summaries of every replay file. Files in the replay directory that
+    /// can't be read as replays are skipped with a warning, so one stray
+    /// file doesn't hide the rest.
+    pub fn info(&self) -> io::Result<Vec<ReplayInfo>> {
+        let mut info = Vec::new();
+
+        for name in self.replay_files()? {
+            let modified = fs::metadata(self.replay_path(&name)?)?.modified()?;
+
+            let commands = match self.load(&name) {
+                Ok(replay) => replay.commands().count(),
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    warn!("Skipping {name}, not a replay: {e}");
+                    continue;
                 }
-            }
+                Err(e) => return Err(e),
+            };
+
+            info.push(ReplayInfo {
+                name,
+                commands,
+                modified,
+            });
+        }
+
+        Ok(info)
+    }
+
+    pub fn load_transcript(&self, name: &str) -> io::Result<String> {
+        fs::read_to_string(self.transcript_path(name)?)
+    }
+
+    pub fn save_transcript(&self, name: &str, transcript: &str) -> io::Result<()> {
+        let transcript_path = self.transcript_path(name)?;
+
+        fs::create_dir_all(self.replay_dir.join(TRANSCRIPT_DIR))?;
+        fs::write(transcript_path, transcript)
+    }
+
+    fn transcript_path(&self, name: &str) -> io::Result<PathBuf> {
+        // Validate the name the same way as replay names
+        self.replay_path(name)?;
+
+        Ok(self.replay_dir.join(TRANSCRIPT_DIR).join(name))
+    }
+
+    /// Delete a replay along with its transcript, if it has one.
+    pub fn delete(&self, name: &str) -> io::Result<()> {
+        fs::remove_file(self.replay_path(name)?)?;
+
+        match fs::remove_file(self.transcript_path(name)?) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Rename a replay along with its transcript, if it has one.
+    pub fn rename(&self, from: &str, to_name: &str) -> io::Result<()> {
+        let to = self.replay_path(to_name)?;
+
+        if to.try_exists()? {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Replay already exists: {}", to.display()),
+            ));
+        }
+
+        fs::rename(self.replay_path(from)?, to)?;
+
+        // The transcript goes with the replay, replacing any left behind by
+        // a replay of the new name
+        let from_transcript = self.transcript_path(from)?;
+        if from_transcript.try_exists()? {
+            fs::rename(from_transcript, self.transcript_path(to_name)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn replay_number(name: &str) -> Option<u32> {
+        static REPLAY_FILE_REGEX: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^replay_([\d]+)$").expect("Invalid regex"));
+
+        REPLAY_FILE_REGEX.captures(name)?[1].parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manage_replay_files() {
+        let replay_dir =
+            std::env::temp_dir().join(format!("synacore-replays-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&replay_dir);
+
+        let replay_manager = ReplayManager::new(&replay_dir);
+        assert_eq!(
+            replay_manager.next_file_path().unwrap(),
+            replay_dir.join("replay_1")
+        );
+
+        for name in ["replay_2", "replay_10", "replay_9"] {
+            let mut replay = ReplayManager::new(&replay_dir);
+            replay.push("look\n").unwrap();
+            replay.push("go north\n").unwrap();
+            replay
+                .save(&replay_dir.join(name), ReplayFormat::Legacy)
+                .unwrap();
         }
 
-        // If no replay files were found, return default file path
-        return Ok(PathBuf::from(&format!("{REPLAY_SAVE_DIR}/replay_1")));
+        replay_manager
+            .save_transcript("replay_9", "output")
+            .unwrap();
+        replay_manager
+            .save_transcript("replay_10", "output")
+            .unwrap();
+
+        replay_manager.rename("replay_9", "bridge").unwrap();
+        assert_eq!(replay_manager.load_transcript("bridge").unwrap(), "output");
+        assert!(replay_manager.load_transcript("replay_9").is_err());
+
+        assert!(replay_manager.rename("replay_2", "replay_10").is_err());
+        assert!(replay_manager.rename("replay_2", "../escape").is_err());
+
+        assert_eq!(
+            replay_manager.replay_files().unwrap(),
+            ["replay_2", "replay_10", "bridge"]
+        );
+        assert_eq!(
+            replay_manager.next_file_path().unwrap(),
+            replay_dir.join("replay_11")
+        );
+        assert_eq!(
+            replay_manager
+                .load("bridge")
+                .unwrap()
+                .commands()
+                .collect::<Vec<_>>(),
+            ["look", "go north"]
+        );
+        assert_eq!(replay_manager.info().unwrap()[0].commands, 2);
+
+        let replays = replay_manager.info().unwrap().len();
+        fs::write(replay_dir.join("notes.bin"), [0xff, 0xfe]).unwrap();
+        assert_eq!(replay_manager.info().unwrap().len(), replays);
+        fs::remove_file(replay_dir.join("notes.bin")).unwrap();
+
+        replay_manager.delete("replay_10").unwrap();
+        assert!(replay_manager.load_transcript("replay_10").is_err());
+        assert!(replay_manager.delete("replay_10").is_err());
+        assert_eq!(
+            replay_manager.last_replay().unwrap(),
+            Some("replay_2".to_string())
+        );
+
+        fs::remove_dir_all(&replay_dir).unwrap();
+    }
+
+    #[test]
+    fn test_structured_round_trip() {
+        let mut replay = Replay {
+            header: ReplayHeader {
+                program_hash: Some(program_hash(&[21, 0])),
+                created: Some("2026-01-01T12:00:00Z".to_string()),
+                author: None,
+            },
+            entries: vec![
+                Entry::Comment("start".to_string()),
+                Entry::Command("take tablet".to_string()),
+                Entry::Expect("Taken.".to_string()),
+                Entry::Checkpoint("foyer".to_string()),
+                Entry::Command("@odd".to_string()),
+                Entry::Command(String::new()),
+            ],
+            branches: vec![],
+        };
+
+        replay
+            .add_branch(Branch {
+                name: "south".to_string(),
+                parent: MAIN_BRANCH.to_string(),
+                fork: 1,
+                entries: vec![Entry::Command("go south".to_string())],
+            })
+            .unwrap();
+
+        let text = replay.to_string(ReplayFormat::Structured);
+        assert_eq!(Replay::parse(&text).unwrap(), replay);
+
+        let legacy = Replay::parse(&replay.to_string(ReplayFormat::Legacy)).unwrap();
+        assert_eq!(legacy.header, ReplayHeader::default());
+        assert_eq!(
+            legacy.commands().collect::<Vec<_>>(),
+            ["take tablet", "@odd", ""]
+        );
+        assert_eq!(legacy.entries.len(), 3);
+    }
+
+    #[test]
+    fn test_recover_journal() {
+        let replay_dir =
+            std::env::temp_dir().join(format!("synacore-journal-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&replay_dir);
+
+        let mut session = ReplayManager::new(&replay_dir);
+        let journal_path = session.start_journal().unwrap();
+        session.push("take tablet\n").unwrap();
+        session
+            .push_entry(Entry::Expect("Taken.".to_string()))
+            .unwrap();
+
+        // A running session's journal is left alone
+        let replay_manager = ReplayManager::new(&replay_dir);
+        assert_eq!(
+            replay_manager
+                .recover_journals(ReplayFormat::Structured)
+                .unwrap(),
+            Vec::<PathBuf>::new()
+        );
+        assert!(journal_path.exists());
+
+        // Simulate a crash, leaving the journal behind unlocked
+        drop(session);
+
+        assert_eq!(replay_manager.replay_files().unwrap(), Vec::<String>::new());
+
+        let recovered = replay_manager
+            .recover_journals(ReplayFormat::Structured)
+            .unwrap();
+        assert_eq!(recovered, [replay_dir.join("replay_1")]);
+        assert!(!journal_path.exists());
+
+        let replay = replay_manager.load("replay_1").unwrap();
+        assert_eq!(
+            replay.entries,
+            [
+                Entry::Command("take tablet".to_string()),
+                Entry::Expect("Taken.".to_string())
+            ]
+        );
+
+        fs::remove_dir_all(&replay_dir).unwrap();
+    }
+
+    #[test]
+    fn test_branches() {
+        let mut replay = Replay::parse(
+            "#! synacore-replay 1\n\
+             look\n\
+             take tablet\n\
+             @expect Taken.\n\
+             use tablet\n\
+             @branch south main 1\n\
+             go south\n\
+             @branch doorway south 2\n\
+             go north\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            replay.branch_names().collect::<Vec<_>>(),
+            [MAIN_BRANCH, "south", "doorway"]
+        );
+
+        let path = replay.path("doorway").unwrap();
+        assert_eq!(
+            path.commands().collect::<Vec<_>>(),
+            ["look", "go south", "go north"]
+        );
+
+        let path = replay.path("south").unwrap();
+        assert_eq!(path.commands().collect::<Vec<_>>(), ["look", "go south"]);
+
+        assert!(replay.path("missing").is_err());
+
+        let too_far = Branch {
+            name: "far".to_string(),
+            parent: "south".to_string(),
+            fork: 3,
+            entries: vec![],
+        };
+        assert!(replay.add_branch(too_far).is_err());
+
+        let duplicate = Branch {
+            name: "south".to_string(),
+            parent: MAIN_BRANCH.to_string(),
+            fork: 0,
+            entries: vec![],
+        };
+        assert!(replay.add_branch(duplicate).is_err());
+
+        assert!(Replay::parse("#! synacore-replay 1\n@branch x missing 0\n").is_err());
     }
 }
@@ -0,0 +1,123 @@
+use std::mem;
+
+use crate::machine::U15_MAX;
+
+/// How a candidate address has to have changed to stay a candidate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    /// Now holds this value
+    Equal(u16),
+
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Filter {
+    fn keeps(&self, before: u16, after: u16) -> bool {
+        match self {
+            Self::Equal(value) => after == *value,
+            Self::Changed => after != before,
+            Self::Unchanged => after == before,
+            Self::Increased => after > before,
+            Self::Decreased => after < before,
+        }
+    }
+}
+
+/// Finds the addresses of game state, like a cheat scanner: start with every
+/// address holding a value, then narrow them down by how they change between
+/// points in time.
+///
+/// Each scan remembers the main memory it was made on, so the next one is
+/// compared against it unless it is given an earlier state to compare
+/// against instead.
+#[derive(Clone, Debug)]
+pub struct Scanner {
+    candidates: Vec<usize>,
+    previous: Vec<u16>,
+}
+
+impl Scanner {
+    /// Start with every address of main memory, for values that aren't known.
+    pub fn new(memory: &[u16]) -> Self {
+        let memory = &memory[..U15_MAX as usize];
+
+        Self {
+            candidates: (0..memory.len()).collect(),
+            previous: memory.to_vec(),
+        }
+    }
+
+    /// Start with the addresses of main memory holding `value`.
+    pub fn search(memory: &[u16], value: u16) -> Self {
+        let mut scanner = Self::new(memory);
+        scanner
+            .candidates
+            .retain(|address| memory[*address] == value);
+
+        scanner
+    }
+
+    /// Keep the candidates that pass `filter` between the last scan and
+    /// `memory`.
+    pub fn narrow(&mut self, memory: &[u16], filter: Filter) {
+        let previous = mem::take(&mut self.previous);
+        self.narrow_since(&previous, memory, filter);
+    }
+
+    /// Keep the candidates that pass `filter` between `before` and `memory`.
+    pub fn narrow_since(&mut self, before: &[u16], memory: &[u16], filter: Filter) {
+        self.candidates
+            .retain(|address| filter.keeps(before[*address], memory[*address]));
+
+        self.previous = memory[..U15_MAX as usize].to_vec();
+    }
+
+    pub fn candidates(&self) -> &[usize] {
+        &self.candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scanner() {
+        let mut memory = vec![0; U15_MAX as usize];
+        memory[10] = 5;
+        memory[20] = 5;
+        memory[30] = 5;
+
+        let mut scanner = Scanner::search(&memory, 5);
+        assert_eq!(scanner.candidates(), [10, 20, 30]);
+
+        let start = memory.clone();
+
+        memory[10] = 6;
+        memory[20] = 4;
+        scanner.narrow(&memory, Filter::Changed);
+        assert_eq!(scanner.candidates(), [10, 20]);
+
+        memory[10] = 7;
+        scanner.narrow(&memory, Filter::Unchanged);
+        assert_eq!(scanner.candidates(), [20]);
+
+        // Compared against the start rather than the last scan
+        let mut scanner = Scanner::search(&start, 5);
+        scanner.narrow_since(&start, &memory, Filter::Increased);
+        assert_eq!(scanner.candidates(), [10]);
+
+        scanner.narrow(&memory, Filter::Equal(8));
+        assert!(scanner.candidates().is_empty());
+
+        let mut scanner = Scanner::new(&memory);
+        memory[40] = 1;
+        scanner.narrow(&memory, Filter::Increased);
+        assert_eq!(scanner.candidates(), [40]);
+        scanner.narrow(&memory, Filter::Decreased);
+        assert!(scanner.candidates().is_empty());
+    }
+}
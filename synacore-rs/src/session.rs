@@ -0,0 +1,604 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use log::{debug, error, info};
+
+use crate::device::OutputSink;
+use crate::diff::Diff;
+use crate::events::Event;
+use crate::machine::{Machine, REGISTER_OFFSET, U15_MAX};
+use crate::meta::{self, MetaCommand, ScanCommand};
+use crate::project::Project;
+use crate::replay::{Entry, ReplayFormat, ReplayManager};
+use crate::scan::Scanner;
+use crate::strings;
+
+/// Number of commands that can be undone with `!undo`.
+const UNDO_LIMIT: usize = 1000;
+
+/// Most candidates `!scan` lists, any more are only counted.
+const SCAN_LIST_LIMIT: usize = 20;
+
+/// Why [`Session::run`] stopped.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stop {
+    /// The program is waiting for a line of input
+    NeedInput,
+
+    Halted,
+
+    /// The program failed with this error
+    Fault(String),
+}
+
+/// What [`Session::handle_line`] did with a line of input.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Handled {
+    /// The line was sent to the program
+    Sent,
+
+    /// The line was blank, so the next command of the replay was sent in its
+    /// place
+    Replayed(String),
+
+    /// The line was a meta command, which replied with this text
+    Reply(String),
+
+    /// `!replay`: play the rest of the replay without waiting for input
+    Replay,
+
+    /// `!quit`: stop playing
+    Quit,
+}
+
+/// A play session: a running machine with the replay being recorded of it,
+/// any replay being played back, and everything the meta commands work on.
+///
+/// Reading input and writing output is left to the caller, which runs the
+/// program with [`Session::run`] and hands each line of input it reads to
+/// [`Session::handle_line`].
+pub struct Session {
+    machine: Machine,
+    replay_manager: ReplayManager,
+
+    /// Entries of the replay being played back that haven't been reached yet
+    autoplay: VecDeque<Entry>,
+
+    /// Checkpoint to stop playing back the replay at
+    until: Option<String>,
+
+    /// Machine state and recording from before each command, for `!undo`
+    history: Vec<(Machine, Vec<Entry>)>,
+
+    /// States remembered with `!save`
+    saved_states: HashMap<String, (Machine, Vec<Entry>)>,
+
+    scanner: Option<Scanner>,
+    project: Project,
+    project_path: PathBuf,
+
+    /// Output since the last line of input, checked by replay expect
+    /// directives
+    command_output: String,
+
+    failed: bool,
+}
+
+impl Session {
+    /// Start a session recording into `replay_manager`, with the addresses
+    /// named in the project file at `project_path` if there is one.
+    pub fn new(
+        machine: Machine,
+        replay_manager: ReplayManager,
+        project_path: &Path,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            machine,
+            replay_manager,
+            autoplay: VecDeque::new(),
+            until: None,
+            history: Vec::new(),
+            saved_states: HashMap::new(),
+            scanner: None,
+            project: Project::open(project_path)?,
+            project_path: project_path.to_path_buf(),
+            command_output: String::new(),
+            failed: false,
+        })
+    }
+
+    /// Play back `entries`, stopping at the checkpoint called `until`.
+    pub fn with_autoplay(mut self, entries: Vec<Entry>, until: Option<String>) -> Self {
+        self.autoplay = entries.into();
+        self.until = until;
+        self
+    }
+
+    /// Journal the recording as it goes, see [`ReplayManager::start_journal`],
+    /// and turn the journal into a replay if the process is interrupted.
+    ///
+    /// This sets the process wide Ctrl-C handler, which exits once the
+    /// journal is saved, so only one session per process can use it.
+    pub fn journal_on_interrupt(&mut self, format: ReplayFormat) -> anyhow::Result<()> {
+        let journal_path = self
+            .replay_manager
+            .start_journal()
+            .context("Error creating replay journal")?;
+
+        let replay_dir = self.replay_manager.replay_dir().to_path_buf();
+        ctrlc::set_handler(move || {
+            let replay_manager = ReplayManager::new(&replay_dir);
+
+            match replay_manager.finalize_journal(&journal_path, format) {
+                Ok(Some(replay_file_path)) => {
+                    info!("Saved replay {}", replay_file_path.display())
+                }
+                Ok(None) => {}
+                Err(e) => error!("Error saving replay: {e}"),
+            }
+
+            std::process::exit(130);
+        })
+        .context("Error setting interrupt handler")
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Whether the program failed or a replay expect directive wasn't met.
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// End the session, handing back the recording to save or discard.
+    pub fn into_replay_manager(self) -> ReplayManager {
+        self.replay_manager
+    }
+
+    /// Run until the program needs input, halts or fails, writing its output
+    /// to `output`. The replay's directives up to its next command are
+    /// handled each time it stops.
+    pub fn run(&mut self, mut output: impl OutputSink) -> io::Result<Stop> {
+        let mut events = self.machine.events();
+
+        let stop = loop {
+            match events.next() {
+                Some(Event::Output(s)) => {
+                    output.write(&s)?;
+                    self.command_output += &s;
+                }
+
+                Some(Event::NeedInput) => break Stop::NeedInput,
+
+                Some(Event::Halted) | None => {
+                    debug!("program execution complete");
+                    break Stop::Halted;
+                }
+
+                Some(Event::Fault(e)) => {
+                    error!("{e}");
+                    self.failed = true;
+                    break Stop::Fault(e);
+                }
+
+                Some(Event::Step { .. }) => {}
+            }
+        };
+
+        self.run_replay_directives()?;
+
+        Ok(stop)
+    }
+
+    /// Take the next command of the replay being played back.
+    pub fn next_replay_command(&mut self) -> Option<String> {
+        match self.autoplay.pop_front() {
+            Some(Entry::Command(command)) => Some(command),
+            _ => None,
+        }
+    }
+
+    /// The next command of the replay being played back.
+    pub fn peek_replay_command(&self) -> Option<&str> {
+        match self.autoplay.front() {
+            Some(Entry::Command(command)) => Some(command),
+            _ => None,
+        }
+    }
+
+    /// Handle a line of input typed by the player: run it if it's a meta
+    /// command, and otherwise send it to the program.
+    pub fn handle_line(&mut self, line: &str) -> io::Result<Handled> {
+        let command = match MetaCommand::parse(line) {
+            None => None,
+            Some(Ok(command)) => Some(command),
+            Some(Err(e)) => return Ok(Handled::Reply(format!("{e}\n"))),
+        };
+
+        match command {
+            Some(MetaCommand::Replay) => Ok(Handled::Replay),
+            Some(MetaCommand::Quit) => Ok(Handled::Quit),
+            Some(command) => self.run_meta(command).map(Handled::Reply),
+
+            None if line == "\n" && self.peek_replay_command().is_some() => {
+                let command = self.next_replay_command().unwrap_or_default();
+                self.send(&format!("{command}\n"))?;
+                Ok(Handled::Replayed(command))
+            }
+
+            None => {
+                self.send(line)?;
+                Ok(Handled::Sent)
+            }
+        }
+    }
+
+    /// Send a line of input to the program as a command, recording it and
+    /// remembering the state before it for `!undo`.
+    pub fn send(&mut self, line: &str) -> io::Result<()> {
+        self.push_history();
+
+        self.replay_manager.push(line)?;
+
+        self.machine.push_input(line);
+        self.command_output.clear();
+
+        Ok(())
+    }
+
+    fn snapshot(&self) -> (Machine, Vec<Entry>) {
+        (self.machine.clone(), self.replay_manager.entries().to_vec())
+    }
+
+    /// Remember the current state for `!undo`, forgetting the oldest once
+    /// there are `UNDO_LIMIT`.
+    fn push_history(&mut self) {
+        if self.history.len() == UNDO_LIMIT {
+            self.history.remove(0);
+        }
+        self.history.push(self.snapshot());
+    }
+
+    fn restore(&mut self, (machine, entries): (Machine, Vec<Entry>)) -> io::Result<()> {
+        self.machine = machine;
+        self.replay_manager.restore(entries)
+    }
+
+    /// Run a meta command other than `!replay` and `!quit`, returning what it
+    /// printed.
+    fn run_meta(&mut self, command: MetaCommand) -> io::Result<String> {
+        let mut reply = String::new();
+
+        match command {
+            MetaCommand::Replay | MetaCommand::Quit => {}
+
+            MetaCommand::Undo => match self.history.pop() {
+                Some(previous) => {
+                    self.restore(previous)?;
+                    let _ = writeln!(reply, "Undone");
+                }
+                None => {
+                    let _ = writeln!(reply, "Nothing to undo");
+                }
+            },
+
+            MetaCommand::Save(name) => {
+                let _ = writeln!(reply, "Saved {name}");
+                self.saved_states.insert(name, self.snapshot());
+            }
+
+            MetaCommand::Load(name) => match self.saved_states.get(&name).cloned() {
+                Some(saved) => {
+                    self.push_history();
+                    self.restore(saved)?;
+                    let _ = writeln!(reply, "Loaded {name}");
+                }
+                None => {
+                    let _ = writeln!(reply, "No saved state named {name}");
+                }
+            },
+
+            MetaCommand::Regs => {
+                let _ = writeln!(reply, "pc: {}", self.machine.pc());
+                for (i, value) in self.machine.registers().iter().enumerate() {
+                    let _ = writeln!(reply, "r{i}: {value}");
+                }
+            }
+
+            MetaCommand::Set { register, value } => {
+                let _ = match self.machine.set_register(register, value) {
+                    Ok(()) => writeln!(reply, "r{register} = {value}"),
+                    Err(e) => writeln!(reply, "{e}"),
+                };
+            }
+
+            MetaCommand::Poke { address, value } => {
+                let _ = match self.machine.write_memory(address, value) {
+                    Ok(()) => writeln!(reply, "{address}: {value}"),
+                    Err(e) => writeln!(reply, "{e}"),
+                };
+            }
+
+            MetaCommand::Peek { address, len } => {
+                let memory = self.machine.memory();
+                let start = (address as usize).min(memory.len());
+                let end = (start + len as usize).min(memory.len());
+
+                for (i, row) in memory[start..end].chunks(8).enumerate() {
+                    let words = row
+                        .iter()
+                        .map(|word| word.to_string())
+                        .collect::<Vec<String>>();
+                    let _ = writeln!(reply, "{}: {}", start + i * 8, words.join(" "));
+                }
+            }
+
+            MetaCommand::Stack => {
+                for (depth, value) in self.machine.stack().iter().rev().enumerate() {
+                    let _ = writeln!(reply, "{depth}: {value}");
+                }
+            }
+
+            MetaCommand::Dis { address, count } => {
+                let memory = self.machine.memory();
+                let found = strings::extract(&memory[..U15_MAX as usize], strings::DEFAULT_MIN_LEN);
+                reply = strings::disassemble(memory, address as usize, count, &found);
+            }
+
+            MetaCommand::Scan(command) => self.scan(command, &mut reply),
+
+            MetaCommand::Def { name, address } => {
+                let defined = self
+                    .project
+                    .define(&name, address as usize)
+                    .and_then(|()| self.project.save(&self.project_path));
+
+                let _ = match defined {
+                    Ok(()) => writeln!(reply, "{name} = {address}"),
+                    Err(e) => writeln!(reply, "{e:#}"),
+                };
+            }
+
+            MetaCommand::Undef(name) => {
+                if !self.project.remove(&name) {
+                    let _ = writeln!(reply, "No address named {name}");
+                } else if let Err(e) = self.project.save(&self.project_path) {
+                    let _ = writeln!(reply, "{e:#}");
+                }
+            }
+
+            MetaCommand::Vars => {
+                for (name, address) in self.project.symbols() {
+                    let _ = writeln!(
+                        reply,
+                        "{name} ({address}): {}",
+                        self.machine.memory()[address]
+                    );
+                }
+            }
+
+            MetaCommand::Diff(name) => {
+                let before = match &name {
+                    Some(name) => self.saved_states.get(name).map(|(saved, _)| saved),
+                    None => self.history.last().map(|(previous, _)| previous),
+                };
+
+                match (before, name) {
+                    (Some(before), _) => {
+                        let diff = Diff::new(&before.state(), &self.machine.state());
+                        let _ = write!(reply, "{}", diff.display(&self.project));
+                    }
+                    (None, Some(name)) => {
+                        let _ = writeln!(reply, "No saved state named {name}");
+                    }
+                    (None, None) => {
+                        let _ = writeln!(reply, "No command to compare against");
+                    }
+                }
+            }
+
+            MetaCommand::Put { name, value } => match self.project.address(&name) {
+                Some(address) => {
+                    let written = match address.checked_sub(REGISTER_OFFSET as usize) {
+                        Some(register) => self.machine.set_register(register as u16, value),
+                        None => self.machine.write_memory(address as u16, value),
+                    };
+
+                    let _ = match written {
+                        Ok(()) => writeln!(reply, "{name} ({address}) = {value}"),
+                        Err(e) => writeln!(reply, "{e}"),
+                    };
+                }
+                None => {
+                    let _ = writeln!(reply, "No address named {name}");
+                }
+            },
+        }
+
+        Ok(reply)
+    }
+
+    fn scan(&mut self, command: ScanCommand, reply: &mut String) {
+        let memory = self.machine.memory();
+
+        match (command, &mut self.scanner) {
+            (ScanCommand::Start(value), _) => {
+                self.scanner = Some(match value {
+                    Some(value) => Scanner::search(memory, value),
+                    None => Scanner::new(memory),
+                });
+            }
+
+            (
+                ScanCommand::Narrow {
+                    filter,
+                    since: None,
+                },
+                Some(scanner),
+            ) => {
+                scanner.narrow(memory, filter);
+            }
+
+            (
+                ScanCommand::Narrow {
+                    filter,
+                    since: Some(name),
+                },
+                Some(scanner),
+            ) => match self.saved_states.get(&name) {
+                Some((saved, _)) => scanner.narrow_since(saved.memory(), memory, filter),
+                None => {
+                    let _ = writeln!(reply, "No saved state named {name}");
+                    return;
+                }
+            },
+
+            (ScanCommand::List, Some(_)) => {}
+
+            (_, None) => {
+                let _ = writeln!(
+                    reply,
+                    "No scan started, start one with {}scan <value|all>",
+                    meta::META_PREFIX
+                );
+                return;
+            }
+        }
+
+        let candidates = self
+            .scanner
+            .as_ref()
+            .map_or(&[][..], |scanner| scanner.candidates());
+
+        let _ = writeln!(reply, "{} candidates", candidates.len());
+        if candidates.len() <= SCAN_LIST_LIMIT {
+            for address in candidates {
+                let _ = writeln!(reply, "{address}: {}", memory[*address]);
+            }
+        }
+    }
+
+    /// Handle the comments, checkpoints and expect directives at the front of
+    /// the replay being played back, up to its next command. Directives are
+    /// carried over into the replay being recorded. Stops playback at the
+    /// `until` checkpoint, and fails the session if an expect directive
+    /// isn't found in the output of the last command.
+    fn run_replay_directives(&mut self) -> io::Result<()> {
+        while !matches!(self.autoplay.front(), None | Some(Entry::Command(_))) {
+            let Some(entry) = self.autoplay.pop_front() else {
+                break;
+            };
+
+            match &entry {
+                Entry::Command(_) | Entry::Comment(_) => {}
+
+                Entry::Checkpoint(name) => {
+                    if self.until.as_ref() == Some(name) {
+                        debug!("Reached checkpoint {name}, stopping replay");
+                        self.autoplay.clear();
+                    }
+                }
+
+                Entry::Expect(text) => {
+                    if !self.command_output.contains(text.as_str()) {
+                        error!("Replay expected output to contain: {text}");
+                        self.failed = true;
+                    }
+                }
+            }
+
+            self.replay_manager.push_entry(entry)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::StringSink;
+    use crate::parse::{IN, JMP, OUT};
+
+    #[test]
+    fn test_session() {
+        // Echo input forever
+        #[rustfmt::skip]
+        let program = vec![
+            IN, REGISTER_OFFSET,
+            OUT, REGISTER_OFFSET,
+            JMP, 0,
+        ];
+
+        let replay_dir =
+            std::env::temp_dir().join(format!("synacore-session-{}", std::process::id()));
+        let autoplay = vec![
+            Entry::Command("ab".to_string()),
+            Entry::Expect("ab".to_string()),
+            Entry::Command("cd".to_string()),
+            Entry::Expect("nope".to_string()),
+        ];
+
+        let mut session = Session::new(
+            Machine::new(program),
+            ReplayManager::new(&replay_dir),
+            &replay_dir.join("project.txt"),
+        )
+        .unwrap()
+        .with_autoplay(autoplay, None);
+
+        let mut output = StringSink::new();
+        let mut run = |session: &mut Session| {
+            assert_eq!(session.run(&mut output).unwrap(), Stop::NeedInput);
+            output.take()
+        };
+
+        assert_eq!(run(&mut session), "");
+        assert_eq!(
+            session.handle_line("!save start\n").unwrap(),
+            Handled::Reply("Saved start\n".to_string())
+        );
+
+        // A blank line plays the next command of the replay
+        assert_eq!(
+            session.handle_line("\n").unwrap(),
+            Handled::Replayed("ab".to_string())
+        );
+        assert_eq!(run(&mut session), "ab\n");
+        assert!(!session.failed());
+
+        assert_eq!(session.handle_line("x\n").unwrap(), Handled::Sent);
+        assert_eq!(run(&mut session), "x\n");
+        assert_eq!(
+            session.handle_line("!undo\n").unwrap(),
+            Handled::Reply("Undone\n".to_string())
+        );
+        assert!(matches!(
+            session.handle_line("!bogus\n").unwrap(),
+            Handled::Reply(reply) if reply.starts_with("unknown command")
+        ));
+        assert_eq!(session.handle_line("!replay\n").unwrap(), Handled::Replay);
+
+        assert_eq!(session.peek_replay_command(), Some("cd"));
+        let command = session.next_replay_command().unwrap();
+        session.send(&format!("{command}\n")).unwrap();
+        assert_eq!(run(&mut session), "cd\n");
+        assert!(session.failed());
+
+        assert_eq!(session.handle_line("!quit\n").unwrap(), Handled::Quit);
+
+        assert_eq!(
+            session.into_replay_manager().entries(),
+            [
+                Entry::Command("ab".to_string()),
+                Entry::Expect("ab".to_string()),
+                Entry::Command("cd".to_string()),
+                Entry::Expect("nope".to_string()),
+            ]
+        );
+    }
+}
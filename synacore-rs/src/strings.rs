@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::machine::REGISTER_OFFSET;
+use crate::parse::{Token, OUT};
+
+/// Shortest string worth reporting, in characters.
+pub const DEFAULT_MIN_LEN: usize = 4;
+
+/// Longest piece of a string shown in a disassembly comment.
+const COMMENT_LEN: usize = 40;
+
+/// How a string is stored in memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StringKind {
+    /// A word holding the length, followed by a word per character
+    LengthPrefixed,
+
+    /// A run of `out` instructions with literal characters
+    Out,
+}
+
+/// A string found in memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Found {
+    pub address: usize,
+    pub kind: StringKind,
+    pub text: String,
+
+    /// Number of words the string takes up
+    pub len: usize,
+}
+
+/// Find the strings in a memory image of at least `min_len` characters, by
+/// address.
+///
+/// Most of the challenge's text is encrypted until it starts, so scan a dump
+/// of a running machine to find it.
+pub fn extract(memory: &[u16], min_len: usize) -> Vec<Found> {
+    let mut found = length_prefixed(memory, min_len);
+    found.extend(out_sequences(memory, min_len));
+    found.sort_by_key(|found| found.address);
+
+    found
+}
+
+fn length_prefixed(memory: &[u16], min_len: usize) -> Vec<Found> {
+    let mut found = Vec::new();
+
+    let mut address = 0;
+    while address < memory.len() {
+        let len = memory[address] as usize;
+        let text = (len >= min_len)
+            .then(|| memory.get(address + 1..address + 1 + len))
+            .flatten()
+            .and_then(|words| words.iter().map(|word| printable(*word)).collect());
+
+        match text {
+            Some(text) => {
+                found.push(Found {
+                    address,
+                    kind: StringKind::LengthPrefixed,
+                    text,
+                    len: len + 1,
+                });
+                address += len + 1;
+            }
+            None => address += 1,
+        }
+    }
+
+    found
+}
+
+fn out_sequences(memory: &[u16], min_len: usize) -> Vec<Found> {
+    let mut found = Vec::new();
+
+    let mut address = 0;
+    while address < memory.len() {
+        let mut text = String::new();
+        let mut end = address;
+
+        while let Some(&[OUT, c, ..]) = memory.get(end..) {
+            match printable(c) {
+                Some(c) => text.push(c),
+                None => break,
+            }
+            end += 2;
+        }
+
+        if text.chars().count() >= min_len {
+            found.push(Found {
+                address,
+                kind: StringKind::Out,
+                text,
+                len: end - address,
+            });
+            address = end;
+        } else {
+            address += 1;
+        }
+    }
+
+    found
+}
+
+/// The character a word stands for if it is one that can be read as text.
+fn printable(word: u16) -> Option<char> {
+    let c = char::from_u32(word as u32)?;
+
+    (c.is_ascii_graphic() || c == ' ' || c == '\n').then_some(c)
+}
+
+/// Disassemble `count` lines starting at `address`, one per line prefixed
+/// with its address, showing the length prefixed strings in `strings` as
+/// strings rather than instructions.
+///
+/// Instructions are commented with the strings they print, and the length
+/// prefixed strings whose address they take as an argument.
+pub fn disassemble(memory: &[u16], address: usize, count: usize, strings: &[Found]) -> String {
+    let strings: BTreeMap<usize, &Found> =
+        strings.iter().map(|found| (found.address, found)).collect();
+
+    let mut output = String::new();
+
+    let mut pc = address;
+    for _ in 0..count {
+        if let Some(found) = strings
+            .get(&pc)
+            .filter(|found| found.kind == StringKind::LengthPrefixed)
+        {
+            let _ = writeln!(output, "{pc}: String({:?})", found.text);
+            pc += found.len;
+            continue;
+        }
+
+        let Some(token) = memory.get(pc..).and_then(Token::parse) else {
+            let _ = writeln!(output, "{pc}: Error: unable to parse");
+            break;
+        };
+
+        let _ = write!(output, "{pc}: {token:?}");
+
+        if let Some(found) = strings
+            .get(&pc)
+            .filter(|found| found.kind == StringKind::Out)
+        {
+            let _ = write!(output, "  ; prints {:?}", shorten(&found.text));
+        }
+
+        for arg in token.args() {
+            if arg >= REGISTER_OFFSET {
+                continue;
+            }
+
+            if let Some(found) = strings
+                .get(&(arg as usize))
+                .filter(|found| found.kind == StringKind::LengthPrefixed)
+            {
+                let _ = write!(output, "  ; {arg}: {:?}", shorten(&found.text));
+            }
+        }
+
+        output.push('\n');
+        pc += token.pc_delta();
+    }
+
+    output
+}
+
+fn shorten(text: &str) -> String {
+    match text.char_indices().nth(COMMENT_LEN) {
+        Some((i, _)) => format!("{}...", &text[..i]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{HALT, SET};
+
+    #[test]
+    fn test_extract() {
+        let mut memory = vec![HALT];
+
+        // 1: a length prefixed string
+        memory.push(5);
+        memory.extend("hello".chars().map(|c| c as u16));
+
+        // 7: a string printed by out instructions, then the address of the
+        // first string
+        for c in "hi there".chars() {
+            memory.extend([OUT, c as u16]);
+        }
+        memory.extend([SET, REGISTER_OFFSET, 1]);
+
+        // 26: too short to count
+        memory.extend([2, 'n' as u16, 'o' as u16]);
+
+        let strings = extract(&memory, DEFAULT_MIN_LEN);
+
+        assert_eq!(
+            strings,
+            [
+                Found {
+                    address: 1,
+                    kind: StringKind::LengthPrefixed,
+                    text: "hello".to_string(),
+                    len: 6,
+                },
+                Found {
+                    address: 7,
+                    kind: StringKind::Out,
+                    text: "hi there".to_string(),
+                    len: 16,
+                },
+            ]
+        );
+
+        let disassembly = disassemble(&memory, 0, 4, &strings);
+        assert_eq!(
+            disassembly,
+            "0: Halt\n\
+             1: String(\"hello\")\n\
+             7: Out(104)  ; prints \"hi there\"\n\
+             9: Out(105)\n"
+        );
+
+        let disassembly = disassemble(&memory, 23, 1, &strings);
+        assert_eq!(disassembly, "23: Set(32768, 1)  ; 1: \"hello\"\n");
+    }
+}
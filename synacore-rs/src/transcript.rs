@@ -0,0 +1,270 @@
+use std::cmp::Reverse;
+use std::fmt::Write;
+
+use crate::machine::{Machine, RunState};
+use crate::replay::Replay;
+
+/// Lines of unchanged context shown around each change in a diff.
+const DIFF_CONTEXT: usize = 3;
+
+/// Play every command in `replay` and return everything the program printed,
+/// with each command echoed on its own line where it was entered.
+///
+/// Playback stops when the program asks for input after the last command,
+/// halts, or fails. A failure is recorded as the last line of the transcript.
+pub fn record(program: Vec<u16>, replay: &Replay) -> String {
+    let mut transcript = String::new();
+    let mut commands = replay.commands();
+
+    let mut machine = Machine::new(program);
+
+    loop {
+        match machine.run() {
+            RunState::Continue => {}
+
+            RunState::BufferedOutput(s) => {
+                transcript += s;
+            }
+
+            RunState::InuptNeeded => match commands.next() {
+                Some(command) => {
+                    let _ = writeln!(transcript, "{command}");
+                    machine.push_input(&format!("{command}\n"));
+                }
+
+                None => break,
+            },
+
+            RunState::Halt => break,
+
+            RunState::Error(e) => {
+                let _ = writeln!(transcript, "\nError: {e}");
+                break;
+            }
+        }
+    }
+
+    transcript
+}
+
+/// Line by line diff of two transcripts, or `None` if they are the same.
+///
+/// Removed lines are prefixed with `-`, added lines with `+`, and each group
+/// of changes is shown with a few lines of context and a line number header.
+/// Transcripts whose lines are all the same but that end differently are
+/// reported with a note rather than an empty diff.
+pub fn diff(expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+
+    if expected.lines().eq(actual.lines()) {
+        return Some("\\ Only the line endings differ\n".to_string());
+    }
+
+    let expected = expected.lines().collect::<Vec<&str>>();
+    let actual = actual.lines().collect::<Vec<&str>>();
+
+    let prefix = expected
+        .iter()
+        .zip(&actual)
+        .take_while(|(e, a)| e == a)
+        .count();
+
+    let suffix = expected[prefix..]
+        .iter()
+        .rev()
+        .zip(actual[prefix..].iter().rev())
+        .take_while(|(e, a)| e == a)
+        .count();
+
+    let expected_changed = &expected[prefix..expected.len() - suffix];
+    let actual_changed = &actual[prefix..actual.len() - suffix];
+
+    let mut lines = expected[..prefix]
+        .iter()
+        .map(|line| (' ', *line))
+        .collect::<Vec<(char, &str)>>();
+    lines.extend(diff_lines(expected_changed, actual_changed));
+    lines.extend(
+        expected[expected.len() - suffix..]
+            .iter()
+            .map(|line| (' ', *line)),
+    );
+
+    let mut output = String::new();
+    let mut expected_line = 1;
+    let mut i = 0;
+
+    while i < lines.len() {
+        if lines[i].0 == ' ' {
+            expected_line += 1;
+            i += 1;
+            continue;
+        }
+
+        // Extend the hunk until there are more than two contexts worth of
+        // unchanged lines in a row
+        let start = i.saturating_sub(DIFF_CONTEXT);
+        let mut end = i;
+        let mut unchanged = 0;
+        while end < lines.len() && unchanged <= DIFF_CONTEXT * 2 {
+            unchanged = if lines[end].0 == ' ' {
+                unchanged + 1
+            } else {
+                0
+            };
+            end += 1;
+        }
+        let end = end - unchanged.saturating_sub(DIFF_CONTEXT);
+
+        let _ = writeln!(output, "@@ line {} @@", expected_line - (i - start));
+        for (kind, line) in &lines[start..end] {
+            let _ = writeln!(output, "{kind}{line}");
+        }
+
+        expected_line += lines[i..end]
+            .iter()
+            .filter(|(kind, _)| *kind != '+')
+            .count();
+        i = end;
+    }
+
+    Some(output)
+}
+
+/// Longest common subsequence diff of two lists of lines.
+///
+/// Uses Hirschberg's algorithm, which only keeps two rows of the table of
+/// subsequence lengths at a time, so that transcripts that differ from early
+/// on don't need a table of every line of one by every line of the other.
+fn diff_lines<'a>(expected: &[&'a str], actual: &[&'a str]) -> Vec<(char, &'a str)> {
+    let mut lines = Vec::with_capacity(expected.len() + actual.len());
+    diff_lines_into(expected, actual, &mut lines);
+
+    lines
+}
+
+fn diff_lines_into<'a>(expected: &[&'a str], actual: &[&'a str], lines: &mut Vec<(char, &'a str)>) {
+    match expected {
+        [] => {
+            lines.extend(actual.iter().map(|line| ('+', *line)));
+        }
+
+        [line] => match actual.iter().position(|a| a == line) {
+            Some(j) => {
+                lines.extend(actual[..j].iter().map(|line| ('+', *line)));
+                lines.push((' ', line));
+                lines.extend(actual[j + 1..].iter().map(|line| ('+', *line)));
+            }
+            None => {
+                lines.push(('-', line));
+                lines.extend(actual.iter().map(|line| ('+', *line)));
+            }
+        },
+
+        _ if actual.is_empty() => {
+            lines.extend(expected.iter().map(|line| ('-', *line)));
+        }
+
+        _ => {
+            // Split actual where the halves of expected line up with it best
+            let mid = expected.len() / 2;
+            let forward = lcs_lengths(expected[..mid].iter().copied(), actual.iter().copied());
+            let backward = lcs_lengths(
+                expected[mid..].iter().rev().copied(),
+                actual.iter().rev().copied(),
+            );
+
+            let split = (0..=actual.len())
+                .max_by_key(|j| (forward[*j] + backward[actual.len() - j], Reverse(*j)))
+                .unwrap_or(0);
+
+            diff_lines_into(&expected[..mid], &actual[..split], lines);
+            diff_lines_into(&expected[mid..], &actual[split..], lines);
+        }
+    }
+}
+
+/// Length of the longest common subsequence of `expected` and each prefix of
+/// `actual`, by prefix length.
+fn lcs_lengths<'a>(
+    expected: impl Iterator<Item = &'a str>,
+    actual: impl Iterator<Item = &'a str> + Clone,
+) -> Vec<usize> {
+    let mut row = vec![0; actual.clone().count() + 1];
+
+    for e in expected {
+        let mut diagonal = 0;
+        for (j, a) in actual.clone().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if e == a {
+                diagonal + 1
+            } else {
+                above.max(row[j])
+            };
+            diagonal = above;
+        }
+    }
+
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{HALT, IN, OUT};
+    use crate::replay::Entry;
+
+    #[test]
+    fn test_record() {
+        #[rustfmt::skip]
+        let program = vec![
+            OUT, '>' as u16,
+            IN, 32768,
+            OUT, 32768,
+            IN, 32768,
+            OUT, 32768,
+            HALT,
+        ];
+
+        let replay = Replay {
+            entries: vec![Entry::Command("a".to_string())],
+            ..Default::default()
+        };
+
+        assert_eq!(record(program, &replay), ">a\na\n");
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff("a\nb\n", "a\nb\n"), None);
+        assert_eq!(
+            diff("a\nb\n", "a\nb").unwrap(),
+            "\\ Only the line endings differ\n"
+        );
+
+        let expected = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let actual = "1\n2\n3\n4\n5\nsix\n7\n8\n9\n10\n11\n";
+
+        assert_eq!(
+            diff(expected, actual).unwrap(),
+            "@@ line 3 @@\n 3\n 4\n 5\n-6\n+six\n 7\n 8\n 9\n 10\n+11\n"
+        );
+
+        let expected = ["a", "b", "c", "d", "e", "f", "g"];
+        let actual = ["b", "x", "d", "a", "f", "g", "y"];
+        let lines = diff_lines(&expected, &actual);
+
+        let side = |kind| {
+            lines
+                .iter()
+                .filter(|(k, _)| *k == ' ' || *k == kind)
+                .map(|(_, line)| *line)
+                .collect::<Vec<&str>>()
+        };
+        assert_eq!(side('-'), expected);
+        assert_eq!(side('+'), actual);
+        assert_eq!(lines.iter().filter(|(k, _)| *k == ' ').count(), 4);
+    }
+}
@@ -0,0 +1,609 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+use std::io;
+use std::mem;
+
+use crate::device::{InputSource, OutputSink};
+use crate::machine::{Engine, Machine, RunState, State, NUM_REGISTERS, REGISTER_OFFSET, U15_MAX};
+use crate::parse::Token;
+
+/// Why translated code stopped running.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exit {
+    /// An `in` found no input, pc is left at the `in`
+    NeedInput,
+
+    Halt,
+
+    /// Execution reached code that wasn't translated, was overwritten, or
+    /// would stop the interpreter with an error, so the interpreter has to
+    /// carry on from pc
+    Fallback,
+}
+
+/// What a translated source file exports, as `TRANSLATED`.
+pub struct Translated {
+    /// Start and end address of every translated block, sorted by start
+    pub blocks: &'static [(usize, usize)],
+
+    /// The words of every block, in the same order, which the memory has to
+    /// match for a block to be run
+    pub code: &'static [u16],
+
+    /// Run from `vm.pc` until the program stops or falls back
+    pub run: fn(&mut Vm) -> Exit,
+}
+
+/// The state translated code runs on, and the driver that hands execution
+/// back and forth between it and the interpreter.
+pub struct Vm {
+    pub pc: usize,
+
+    /// Main memory followed by the registers
+    pub memory: Vec<u16>,
+
+    pub stack: Vec<u16>,
+    pub input: VecDeque<char>,
+    pub output: String,
+
+    /// The translated code at its addresses, and zero elsewhere
+    original: Vec<u16>,
+
+    /// Whether each address is part of a translated block
+    code: Vec<bool>,
+
+    /// Set once any translated code may have been overwritten
+    code_written: bool,
+}
+
+impl Vm {
+    pub fn new(program: Vec<u16>, translated: &Translated) -> Self {
+        Self::from_state(Machine::new(program).into_state(), translated)
+    }
+
+    /// Start from a machine's state, which doesn't have to be the one the
+    /// code was translated from: blocks that don't match its memory are left
+    /// to the interpreter.
+    pub fn from_state(state: State, translated: &Translated) -> Self {
+        let mut original = vec![0; U15_MAX as usize];
+        let mut code = vec![false; U15_MAX as usize];
+
+        let mut words = translated.code;
+        for (start, end) in translated.blocks {
+            let (block, rest) = words.split_at(end - start);
+            original[*start..*end].copy_from_slice(block);
+            code[*start..*end].fill(true);
+            words = rest;
+        }
+
+        let mut vm = Self {
+            pc: state.pc,
+            memory: state.memory,
+            stack: state.stack,
+            input: VecDeque::new(),
+            output: String::new(),
+            original,
+            code,
+            code_written: false,
+        };
+        vm.check_code(translated);
+
+        vm
+    }
+
+    /// Write a word of memory from translated code, returning true if it was
+    /// part of a translated block, in which case the translated code must
+    /// fall back before running anything else.
+    pub fn write(&mut self, address: usize, value: u16) -> bool {
+        self.memory[address] = value;
+
+        if self.code.get(address) == Some(&true) {
+            self.code_written = true;
+            return true;
+        }
+
+        false
+    }
+
+    /// Whether the code from `start` to `end` is no longer what was translated.
+    pub fn is_dirty(&self, start: usize, end: usize) -> bool {
+        self.code_written && self.memory[start..end] != self.original[start..end]
+    }
+
+    /// Run until the program halts, fails, or needs input, using the
+    /// interpreter wherever the translated code can't be used.
+    pub fn run(&mut self, translated: &Translated) -> RunState {
+        loop {
+            match (translated.run)(self) {
+                Exit::NeedInput => return RunState::InuptNeeded,
+                Exit::Halt => return RunState::Halt,
+                Exit::Fallback => {
+                    if let Some(run_state) = self.interpret(translated) {
+                        return run_state;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Like [`Machine::run_with`], but running translated code.
+    pub fn run_with(
+        &mut self,
+        translated: &Translated,
+        mut input: impl InputSource,
+        mut output: impl OutputSink,
+    ) -> io::Result<RunState> {
+        loop {
+            let run_state = self.run(translated);
+
+            output.write(&mem::take(&mut self.output))?;
+
+            if run_state != RunState::InuptNeeded {
+                output.flush()?;
+                return Ok(run_state);
+            }
+
+            output.flush()?;
+
+            match input.read_line()? {
+                Some(line) => self.input.extend(line.chars()),
+                None => return Ok(run_state),
+            }
+        }
+    }
+
+    /// Interpret instructions until reaching the start of a translated block
+    /// that hasn't been overwritten, or the program stops.
+    fn interpret(&mut self, translated: &Translated) -> Option<RunState> {
+        // Usually only a few instructions run here, too few to be worth
+        // filling a decode cache for
+        let mut machine = Machine::from_state(State {
+            pc: self.pc,
+            memory: mem::take(&mut self.memory),
+            stack: mem::take(&mut self.stack),
+        })
+        .with_engine(Engine::Uncached);
+        machine.push_input(&self.input.drain(..).collect::<String>());
+
+        let stop = loop {
+            match machine.run_once() {
+                RunState::Continue => {
+                    if self.is_entry(translated, machine.pc(), machine.memory()) {
+                        break None;
+                    }
+                }
+
+                RunState::BufferedOutput(s) => self.output += s,
+
+                run_state => break Some(run_state.clone()),
+            }
+        };
+
+        self.output += &machine.flush_output_buffer();
+        self.input.extend(machine.take_input().chars());
+
+        let state = machine.into_state();
+        self.pc = state.pc;
+        self.memory = state.memory;
+        self.stack = state.stack;
+
+        self.check_code(translated);
+
+        stop
+    }
+
+    /// Work out whether any translated code differs from the memory.
+    fn check_code(&mut self, translated: &Translated) {
+        self.code_written = translated
+            .blocks
+            .iter()
+            .any(|(start, end)| self.memory[*start..*end] != self.original[*start..*end]);
+    }
+
+    fn is_entry(&self, translated: &Translated, pc: usize, memory: &[u16]) -> bool {
+        match translated
+            .blocks
+            .binary_search_by_key(&pc, |(start, _)| *start)
+        {
+            Ok(i) => {
+                let (start, end) = translated.blocks[i];
+                memory[start..end] == self.original[start..end]
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Instructions of a program found by following fallthroughs and jumps to
+/// literal addresses from the entry points, and the addresses that start
+/// blocks: entry points, jump targets and the return address of every call.
+///
+/// Most code is only reached through jumps to addresses in registers, so
+/// whatever follows a `jmp`, `ret` or `halt` is taken to be an entry point
+/// as well. When that is really data it is at worst translated needlessly.
+#[derive(Debug, Default, PartialEq)]
+struct Analysis {
+    instructions: BTreeMap<usize, Token>,
+    leaders: BTreeSet<usize>,
+}
+
+impl Analysis {
+    fn new(memory: &[u16], entries: &[usize]) -> Self {
+        let mut analysis = Self::default();
+        analysis.leaders.extend(entries);
+
+        let mut pending = entries.to_vec();
+
+        while let Some(pc) = pending.pop() {
+            if analysis.instructions.contains_key(&pc) {
+                continue;
+            }
+
+            let Some(token) = memory
+                .get(pc..)
+                .and_then(Token::parse)
+                .filter(|token| !matches!(token, Token::Unknown(_)))
+            else {
+                continue;
+            };
+            analysis.instructions.insert(pc, token);
+
+            let next = pc + token.pc_delta();
+            let target = match token {
+                Token::Jmp(target)
+                | Token::Jt(_, target)
+                | Token::Jf(_, target)
+                | Token::Call(target) => Some(target),
+                _ => None,
+            };
+
+            if let Some(target) = target.filter(|target| *target < REGISTER_OFFSET) {
+                analysis.leaders.insert(target as usize);
+                pending.push(target as usize);
+            }
+
+            match token {
+                Token::Call(_) | Token::Jmp(_) | Token::Ret() | Token::Halt => {
+                    analysis.leaders.insert(next);
+                    pending.push(next);
+                }
+                _ => pending.push(next),
+            }
+        }
+
+        analysis
+            .leaders
+            .retain(|pc| analysis.instructions.contains_key(pc));
+
+        analysis
+    }
+}
+
+/// Translate the code found in a machine's memory, starting from address 0
+/// and its pc, into a Rust source file exporting a [`Translated`]. Use the
+/// state at the first prompt to translate code a program decrypts when it
+/// starts.
+///
+/// The file is one big `match` on pc with an arm per block. Anything the
+/// translation can't handle, like a jump to an address only known at run
+/// time that isn't the start of a block, falls back to the interpreter.
+pub fn translate(state: &State) -> String {
+    let memory = &state.memory[..U15_MAX as usize];
+    let analysis = Analysis::new(memory, &[0, state.pc]);
+
+    let mut arms = String::new();
+    let mut blocks = Vec::new();
+
+    for leader in &analysis.leaders {
+        let mut body = String::new();
+        let mut pc = *leader;
+
+        loop {
+            let Some(token) = analysis.instructions.get(&pc) else {
+                let _ = writeln!(body, "vm.pc = {pc};\nreturn Exit::Fallback;");
+                break;
+            };
+
+            let next = pc + token.pc_delta();
+            let ends_block = translate_token(&mut body, pc, *token);
+            pc = next;
+
+            if ends_block {
+                break;
+            }
+
+            if analysis.leaders.contains(&pc) {
+                let _ = writeln!(body, "vm.pc = {pc};\ncontinue;");
+                break;
+            }
+        }
+
+        blocks.push((*leader, pc));
+
+        let _ = writeln!(
+            arms,
+            "{leader} => {{\nif vm.is_dirty({leader}, {pc}) {{\nreturn Exit::Fallback;\n}}\n{body}}}"
+        );
+    }
+
+    let code: Vec<u16> = blocks
+        .iter()
+        .flat_map(|(start, end)| &memory[*start..*end])
+        .copied()
+        .collect();
+
+    let mut output = String::new();
+    let _ = writeln!(
+        output,
+        "// Translated from a Synacor program by `synacore-rs translate`, do not edit.\n"
+    );
+    let _ = writeln!(
+        output,
+        "#![allow(clippy::all, unreachable_code, unused_parens, unused_variables)]\n"
+    );
+    let _ = writeln!(
+        output,
+        "use synacore_rs::translate::{{Exit, Translated, Vm}};\n"
+    );
+    let _ = writeln!(
+        output,
+        "pub const TRANSLATED: Translated = Translated {{\n    blocks: BLOCKS,\n    code: CODE,\n    run,\n}};\n"
+    );
+    let _ = writeln!(output, "const BLOCKS: &[(usize, usize)] = &{blocks:?};\n");
+    let _ = writeln!(output, "const CODE: &[u16] = &{code:?};\n");
+    let _ = writeln!(
+        output,
+        "fn run(vm: &mut Vm) -> Exit {{\nloop {{\nmatch vm.pc {{\n{arms}_ => return Exit::Fallback,\n}}\n}}\n}}"
+    );
+
+    indent(&output)
+}
+
+/// Write the statements for one instruction, returning true if it always
+/// leaves the block.
+fn translate_token(body: &mut String, pc: usize, token: Token) -> bool {
+    let next = pc + token.pc_delta();
+    let fallback = format!("{{\nvm.pc = {pc};\nreturn Exit::Fallback;\n}}");
+
+    // Instructions with arguments the interpreter stops with an error on
+    // are left to it to report
+    let valid_value = |arg: u16| arg < REGISTER_OFFSET + NUM_REGISTERS;
+    let args_valid = match token {
+        Token::Set(a, b) => {
+            (REGISTER_OFFSET..REGISTER_OFFSET + NUM_REGISTERS).contains(&a) && valid_value(b)
+        }
+        Token::Push(a)
+        | Token::Pop(a)
+        | Token::Jmp(a)
+        | Token::Call(a)
+        | Token::Out(a)
+        | Token::In(a) => valid_value(a),
+        Token::Jt(a, b)
+        | Token::Jf(a, b)
+        | Token::Not(a, b)
+        | Token::Rmem(a, b)
+        | Token::Wmem(a, b) => valid_value(a) && valid_value(b),
+        Token::Eq(a, b, c)
+        | Token::Gt(a, b, c)
+        | Token::Add(a, b, c)
+        | Token::Mult(a, b, c)
+        | Token::Mod(a, b, c)
+        | Token::And(a, b, c)
+        | Token::Or(a, b, c) => valid_value(a) && valid_value(b) && valid_value(c),
+        Token::Halt | Token::Ret() | Token::Noop => true,
+        Token::Unknown(_) => false,
+    };
+
+    if !args_valid {
+        let _ = writeln!(body, "vm.pc = {pc};\nreturn Exit::Fallback;");
+        return true;
+    }
+
+    let value = |arg: u16| match arg {
+        0..REGISTER_OFFSET => arg.to_string(),
+        _ => format!("vm.memory[{arg}]"),
+    };
+
+    // Writes to registers can't change code, writes to memory may
+    let write = |destination: u16, expression: String| {
+        match destination {
+        REGISTER_OFFSET.. => format!("vm.memory[{destination}] = {expression};\n"),
+        _ => format!(
+            "let value = {expression};\nif vm.write({destination}, value) {{\nvm.pc = {next};\nreturn Exit::Fallback;\n}}\n"
+        ),
+    }
+    };
+
+    let _ = writeln!(body, "// {pc}: {token:?}");
+
+    let statements = match token {
+        Token::Halt => format!("vm.pc = {pc};\nreturn Exit::Halt;\n"),
+        Token::Set(a, b) => write(a, value(b)),
+        Token::Push(a) => format!("vm.stack.push({});\n", value(a)),
+        Token::Pop(a) => format!(
+            "let Some(popped) = vm.stack.pop() else {fallback};\n{}",
+            write(a, "popped".to_string())
+        ),
+        Token::Eq(a, b, c) => write(a, format!("({} == {}) as u16", value(b), value(c))),
+        Token::Gt(a, b, c) => write(a, format!("({} > {}) as u16", value(b), value(c))),
+        Token::Add(a, b, c) => write(
+            a,
+            format!("(({} as u32 + {} as u32) % {U15_MAX}) as u16", value(b), value(c)),
+        ),
+        Token::Mult(a, b, c) => write(
+            a,
+            format!("(({} as u32 * {} as u32) % {U15_MAX}) as u16", value(b), value(c)),
+        ),
+        Token::Mod(a, b, c) => format!(
+            "let divisor = {};\nif divisor == 0 {fallback}\n{}",
+            value(c),
+            write(a, format!("{} % divisor", value(b)))
+        ),
+        Token::And(a, b, c) => write(a, format!("({} & {}) % {U15_MAX}", value(b), value(c))),
+        Token::Or(a, b, c) => write(a, format!("({} | {}) % {U15_MAX}", value(b), value(c))),
+        Token::Not(a, b) => write(a, format!("(!{}) % {U15_MAX}", value(b))),
+        Token::Rmem(a, b) => format!(
+            "let Some(&read) = vm.memory.get({} as usize) else {fallback};\n{}",
+            value(b),
+            write(a, "read".to_string())
+        ),
+        Token::Wmem(a, b) => format!(
+            "let address = {} as usize;\nif address >= vm.memory.len() {fallback}\nlet value = {};\nif vm.write(address, value) {{\nvm.pc = {next};\nreturn Exit::Fallback;\n}}\n",
+            value(a),
+            value(b)
+        ),
+        Token::Jmp(a) => format!("vm.pc = {} as usize;\ncontinue;\n", value(a)),
+        Token::Jt(a, b) => format!(
+            "if {} != 0 {{\nvm.pc = {} as usize;\ncontinue;\n}}\n",
+            value(a),
+            value(b)
+        ),
+        Token::Jf(a, b) => format!(
+            "if {} == 0 {{\nvm.pc = {} as usize;\ncontinue;\n}}\n",
+            value(a),
+            value(b)
+        ),
+        Token::Call(a) => format!(
+            "vm.stack.push({next});\nvm.pc = {} as usize;\ncontinue;\n",
+            value(a)
+        ),
+        Token::Ret() => format!(
+            "let Some(address) = vm.stack.pop() else {fallback};\nvm.pc = address as usize;\ncontinue;\n"
+        ),
+        Token::Out(a) => match char::from_u32(a as u32) {
+            Some(c) if a < REGISTER_OFFSET => format!("vm.output.push({c:?});\n"),
+            _ => format!(
+                "let Some(c) = char::from_u32({} as u32) else {fallback};\nvm.output.push(c);\n",
+                value(a)
+            ),
+        },
+        Token::In(a) => format!(
+            "let Some(c) = vm.input.pop_front() else {{\nvm.pc = {pc};\nreturn Exit::NeedInput;\n}};\n{}",
+            write(a, "c as u16".to_string())
+        ),
+        Token::Noop => String::new(),
+        Token::Unknown(_) => unreachable!(),
+    };
+
+    body.push_str(&statements);
+
+    matches!(
+        token,
+        Token::Halt | Token::Jmp(_) | Token::Call(_) | Token::Ret()
+    )
+}
+
+/// Indent generated code by its braces.
+fn indent(source: &str) -> String {
+    let mut output = String::with_capacity(source.len() * 2);
+    let mut depth: usize = 0;
+
+    for line in source.lines() {
+        let line = line.trim();
+
+        if line.starts_with('}') {
+            depth = depth.saturating_sub(1);
+        }
+
+        if !line.is_empty() {
+            output.extend(std::iter::repeat_n("    ", depth));
+        }
+        output += line;
+        output.push('\n');
+
+        if line.ends_with('{') {
+            depth += 1;
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{StringSink, StringSource};
+    use crate::parse::{CALL, EQ, HALT, IN, JF, NOOP, OUT, RET};
+
+    #[rustfmt::skip]
+    fn program() -> Vec<u16> {
+        vec![
+            // 0: read a character and print it until it is "q"
+            CALL, 6,
+            JF, REGISTER_OFFSET, 0,
+            HALT,
+            // 6: r0 = 0 if the character read is "q"
+            IN, REGISTER_OFFSET + 1,
+            OUT, REGISTER_OFFSET + 1,
+            EQ, REGISTER_OFFSET, REGISTER_OFFSET + 1, 'q' as u16,
+            RET,
+            // 15: data
+            'x' as u16, 'y' as u16,
+        ]
+    }
+
+    #[test]
+    fn test_analysis() {
+        let analysis = Analysis::new(&program(), &[0]);
+
+        assert_eq!(
+            analysis.leaders.iter().copied().collect::<Vec<usize>>(),
+            [0, 2, 6]
+        );
+        assert_eq!(
+            analysis
+                .instructions
+                .keys()
+                .copied()
+                .collect::<Vec<usize>>(),
+            [0, 2, 5, 6, 8, 10, 14]
+        );
+
+        let source = translate(&Machine::new(program()).into_state());
+        assert!(source.contains("        6 => {\n"));
+        assert!(source.contains("const BLOCKS: &[(usize, usize)] = &[(0, 2), (2, 6), (6, 15)];"));
+        assert!(!source.contains("15 => {"));
+    }
+
+    #[test]
+    fn test_fallback() {
+        // A hand-written translation of the halt at 5 which also prints "!",
+        // with everything else left to the interpreter
+        let translated = Translated {
+            blocks: &[(5, 6)],
+            code: &[HALT],
+            run: |vm| {
+                if vm.pc == 5 && !vm.is_dirty(5, 6) {
+                    vm.output.push('!');
+                    return Exit::Halt;
+                }
+
+                Exit::Fallback
+            },
+        };
+
+        let mut vm = Vm::new(program(), &translated);
+        let mut output = StringSink::new();
+        let run_state = vm
+            .run_with(&translated, StringSource::new("a\nbq"), &mut output)
+            .unwrap();
+
+        assert_eq!(run_state, RunState::Halt);
+        assert_eq!(output.contents(), "a\nbq!");
+
+        // A translation of different code doesn't apply to this program
+        let translated = Translated {
+            code: &[NOOP],
+            ..translated
+        };
+
+        let mut vm = Vm::new(program(), &translated);
+        let mut output = StringSink::new();
+        let run_state = vm
+            .run_with(&translated, StringSource::new("q"), &mut output)
+            .unwrap();
+
+        assert_eq!(run_state, RunState::Halt);
+        assert_eq!(output.contents(), "q");
+    }
+}
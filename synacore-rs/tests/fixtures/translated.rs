@@ -0,0 +1,228 @@
+// Translated from a Synacor program by `synacore-rs translate`, do not edit.
+
+#![allow(clippy::all, unreachable_code, unused_parens, unused_variables)]
+
+use synacore_rs::translate::{Exit, Translated, Vm};
+
+pub const TRANSLATED: Translated = Translated {
+    blocks: BLOCKS,
+    code: CODE,
+    run,
+};
+
+const BLOCKS: &[(usize, usize)] = &[(0, 4), (4, 46), (46, 55), (55, 61), (61, 75), (75, 77), (77, 103), (103, 104), (104, 115)];
+
+const CODE: &[u16] = &[2, 0, 19, 62, 20, 32768, 4, 32769, 32768, 10, 7, 32769, 46, 4, 32769, 32768, 113, 7, 32769, 103, 10, 32770, 32770, 31, 9, 32770, 32770, 32768, 12, 32770, 32770, 32767, 15, 32771, 116, 9, 32771, 32771, 1, 16, 116, 32771, 2, 32768, 6, 4, 3, 32768, 8, 32768, 55, 19, 32768, 6, 46, 2, 0, 19, 32, 17, 104, 15, 32771, 116, 5, 32769, 32771, 3, 8, 32769, 75, 19, 43, 6, 77, 19, 45, 21, 14, 32773, 32770, 13, 32773, 32773, 1, 1, 32774, 88, 19, 97, 9, 32774, 32774, 1, 16, 32774, 98, 19, 10, 19, 62, 6, 4, 0, 11, 32772, 32770, 10, 9, 32772, 32772, 48, 19, 32772, 18];
+
+fn run(vm: &mut Vm) -> Exit {
+    loop {
+        match vm.pc {
+            0 => {
+                if vm.is_dirty(0, 4) {
+                    return Exit::Fallback;
+                }
+                // 0: Push(0)
+                vm.stack.push(0);
+                // 2: Out(62)
+                vm.output.push('>');
+                vm.pc = 4;
+                continue;
+            }
+            4 => {
+                if vm.is_dirty(4, 46) {
+                    return Exit::Fallback;
+                }
+                // 4: In(32768)
+                let Some(c) = vm.input.pop_front() else {
+                    vm.pc = 4;
+                    return Exit::NeedInput;
+                };
+                vm.memory[32768] = c as u16;
+                // 6: Eq(32769, 32768, 10)
+                vm.memory[32769] = (vm.memory[32768] == 10) as u16;
+                // 10: Jt(32769, 46)
+                if vm.memory[32769] != 0 {
+                    vm.pc = 46 as usize;
+                    continue;
+                }
+                // 13: Eq(32769, 32768, 113)
+                vm.memory[32769] = (vm.memory[32768] == 113) as u16;
+                // 17: Jt(32769, 103)
+                if vm.memory[32769] != 0 {
+                    vm.pc = 103 as usize;
+                    continue;
+                }
+                // 20: Mult(32770, 32770, 31)
+                vm.memory[32770] = ((vm.memory[32770] as u32 * 31 as u32) % 32768) as u16;
+                // 24: Add(32770, 32770, 32768)
+                vm.memory[32770] = ((vm.memory[32770] as u32 + vm.memory[32768] as u32) % 32768) as u16;
+                // 28: And(32770, 32770, 32767)
+                vm.memory[32770] = (vm.memory[32770] & 32767) % 32768;
+                // 32: Rmem(32771, 116)
+                let Some(&read) = vm.memory.get(116 as usize) else {
+                    vm.pc = 32;
+                    return Exit::Fallback;
+                };
+                vm.memory[32771] = read;
+                // 35: Add(32771, 32771, 1)
+                vm.memory[32771] = ((vm.memory[32771] as u32 + 1 as u32) % 32768) as u16;
+                // 39: Wmem(116, 32771)
+                let address = 116 as usize;
+                if address >= vm.memory.len() {
+                    vm.pc = 39;
+                    return Exit::Fallback;
+                }
+                let value = vm.memory[32771];
+                if vm.write(address, value) {
+                    vm.pc = 42;
+                    return Exit::Fallback;
+                }
+                // 42: Push(32768)
+                vm.stack.push(vm.memory[32768]);
+                // 44: Jmp(4)
+                vm.pc = 4 as usize;
+                continue;
+            }
+            46 => {
+                if vm.is_dirty(46, 55) {
+                    return Exit::Fallback;
+                }
+                // 46: Pop(32768)
+                let Some(popped) = vm.stack.pop() else {
+                    vm.pc = 46;
+                    return Exit::Fallback;
+                };
+                vm.memory[32768] = popped;
+                // 48: Jf(32768, 55)
+                if vm.memory[32768] == 0 {
+                    vm.pc = 55 as usize;
+                    continue;
+                }
+                // 51: Out(32768)
+                let Some(c) = char::from_u32(vm.memory[32768] as u32) else {
+                    vm.pc = 51;
+                    return Exit::Fallback;
+                };
+                vm.output.push(c);
+                // 53: Jmp(46)
+                vm.pc = 46 as usize;
+                continue;
+            }
+            55 => {
+                if vm.is_dirty(55, 61) {
+                    return Exit::Fallback;
+                }
+                // 55: Push(0)
+                vm.stack.push(0);
+                // 57: Out(32)
+                vm.output.push(' ');
+                // 59: Call(104)
+                vm.stack.push(61);
+                vm.pc = 104 as usize;
+                continue;
+            }
+            61 => {
+                if vm.is_dirty(61, 75) {
+                    return Exit::Fallback;
+                }
+                // 61: Rmem(32771, 116)
+                let Some(&read) = vm.memory.get(116 as usize) else {
+                    vm.pc = 61;
+                    return Exit::Fallback;
+                };
+                vm.memory[32771] = read;
+                // 64: Gt(32769, 32771, 3)
+                vm.memory[32769] = (vm.memory[32771] > 3) as u16;
+                // 68: Jf(32769, 75)
+                if vm.memory[32769] == 0 {
+                    vm.pc = 75 as usize;
+                    continue;
+                }
+                // 71: Out(43)
+                vm.output.push('+');
+                // 73: Jmp(77)
+                vm.pc = 77 as usize;
+                continue;
+            }
+            75 => {
+                if vm.is_dirty(75, 77) {
+                    return Exit::Fallback;
+                }
+                // 75: Out(45)
+                vm.output.push('-');
+                vm.pc = 77;
+                continue;
+            }
+            77 => {
+                if vm.is_dirty(77, 103) {
+                    return Exit::Fallback;
+                }
+                // 77: Noop
+                // 78: Not(32773, 32770)
+                vm.memory[32773] = (!vm.memory[32770]) % 32768;
+                // 81: Or(32773, 32773, 1)
+                vm.memory[32773] = (vm.memory[32773] | 1) % 32768;
+                // 85: Set(32774, 88)
+                vm.memory[32774] = 88;
+                // 88: Out(97)
+                vm.output.push('a');
+                // 90: Add(32774, 32774, 1)
+                vm.memory[32774] = ((vm.memory[32774] as u32 + 1 as u32) % 32768) as u16;
+                // 94: Wmem(32774, 98)
+                let address = vm.memory[32774] as usize;
+                if address >= vm.memory.len() {
+                    vm.pc = 94;
+                    return Exit::Fallback;
+                }
+                let value = 98;
+                if vm.write(address, value) {
+                    vm.pc = 97;
+                    return Exit::Fallback;
+                }
+                // 97: Out(10)
+                vm.output.push('\n');
+                // 99: Out(62)
+                vm.output.push('>');
+                // 101: Jmp(4)
+                vm.pc = 4 as usize;
+                continue;
+            }
+            103 => {
+                if vm.is_dirty(103, 104) {
+                    return Exit::Fallback;
+                }
+                // 103: Halt
+                vm.pc = 103;
+                return Exit::Halt;
+            }
+            104 => {
+                if vm.is_dirty(104, 115) {
+                    return Exit::Fallback;
+                }
+                // 104: Mod(32772, 32770, 10)
+                let divisor = 10;
+                if divisor == 0 {
+                    vm.pc = 104;
+                    return Exit::Fallback;
+                }
+                vm.memory[32772] = vm.memory[32770] % divisor;
+                // 108: Add(32772, 32772, 48)
+                vm.memory[32772] = ((vm.memory[32772] as u32 + 48 as u32) % 32768) as u16;
+                // 112: Out(32772)
+                let Some(c) = char::from_u32(vm.memory[32772] as u32) else {
+                    vm.pc = 112;
+                    return Exit::Fallback;
+                };
+                vm.output.push(c);
+                // 114: Ret
+                let Some(address) = vm.stack.pop() else {
+                    vm.pc = 114;
+                    return Exit::Fallback;
+                };
+                vm.pc = address as usize;
+                continue;
+            }
+            _ => return Exit::Fallback,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Checks the translation of a small program against the interpreter.
+//!
+//! `fixtures/echo.txt` prints each line it reads reversed, followed by a
+//! checksum digit, a `+` or `-` from comparing the character count, and a
+//! letter from an `out` that rewrites itself after the first line. Between
+//! them it uses every opcode, and the rewrite makes the translated code fall
+//! back to the interpreter.
+
+use std::path::Path;
+
+use synacore_rs::device::{StringSink, StringSource};
+use synacore_rs::loader::{self, Format};
+use synacore_rs::machine::{Machine, RunState};
+use synacore_rs::translate::{translate, Vm};
+
+// Generated, so left as the translator writes it
+#[rustfmt::skip]
+#[path = "fixtures/translated.rs"]
+mod translated;
+
+const INPUT: &str = "hello\nab\nxyzw\nq\n";
+
+fn program() -> Vec<u16> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/echo.txt");
+    loader::load(&path, Some(Format::WordText)).unwrap()
+}
+
+#[test]
+fn test_fixture_is_up_to_date() {
+    let source = translate(&Machine::new(program()).into_state());
+
+    assert!(
+        source == include_str!("fixtures/translated.rs"),
+        "The translation changed, regenerate it with \
+         `synacore-rs -f word-text -p tests/fixtures/echo.txt translate tests/fixtures/translated.rs`"
+    );
+}
+
+#[test]
+fn test_translated_matches_interpreter() {
+    let mut machine = Machine::new(program());
+    let mut machine_output = StringSink::new();
+    let machine_run_state = machine
+        .run_with(StringSource::new(INPUT), &mut machine_output)
+        .unwrap()
+        .clone();
+
+    let mut vm = Vm::new(program(), &translated::TRANSLATED);
+    let mut vm_output = StringSink::new();
+    let vm_run_state = vm
+        .run_with(
+            &translated::TRANSLATED,
+            StringSource::new(INPUT),
+            &mut vm_output,
+        )
+        .unwrap();
+
+    assert_eq!(machine_run_state, RunState::Halt);
+    assert_eq!(
+        machine_output.contents(),
+        ">olleh 4+a\n>ba 1+b\n>wzyx 1+b\n>"
+    );
+
+    assert_eq!(vm_run_state, machine_run_state);
+    assert_eq!(vm_output.contents(), machine_output.contents());
+
+    let state = machine.into_state();
+    assert_eq!(vm.pc, state.pc);
+    assert_eq!(vm.memory, state.memory);
+    assert_eq!(vm.stack, state.stack);
+}